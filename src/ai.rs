@@ -0,0 +1,449 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::{CollidingEntities, QueryFilter, ReadRapierContext, Sensor, Velocity};
+
+use crate::{
+    car::{CarOut, StuckTimer},
+    impacts::on_ground,
+    input::{BOOST_FACTOR, PUSH_FACTOR, TURN_FACTOR},
+    movement::{apply_movement, CarMovement, CarMovements},
+    state::GameState,
+};
+
+pub struct AiPlugin;
+
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(AiSettings::from_args())
+            .init_resource::<RacingLine>()
+            .add_systems(
+                Update,
+                (drive_ai_cars.before(apply_movement), recover_ai_cars).run_if(in_state(GameState::Running)),
+            )
+            // Cars go back to the grid on restart, so their place on the line has to be found again
+            .add_systems(OnExit(GameState::GameOver), reset_ai_drivers);
+    }
+}
+
+// How far ahead of the car the terrain is probed for drops and steep dune faces
+const PROBE_DISTANCE: f32 = 20.0;
+// Ground normals flatter than this are fine to drive up at full speed
+const STEEP_SLOPE: f32 = 0.75;
+// Crawling slower than this while on the throttle means the car is wedged against something
+const WEDGED_SPEED: f32 = 2.0;
+const WEDGED_SECONDS: f32 = 2.0;
+const REVERSE_SECONDS: f32 = 1.5;
+// Recovered cars are dropped from this high above the ground under the racing line
+const RECOVERY_HEIGHT: f32 = 5.0;
+
+/// How well an AI car drives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiDifficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+struct DifficultyTuning {
+    /// Speed (m/s) on a straight
+    top_speed: f32,
+    /// Fraction of top speed kept through the sharpest corners
+    corner_speed: f32,
+    /// Distance (m) along the racing line the car aims at
+    lookahead: f32,
+    /// Turn per radian of heading error, 1 is full lock at one radian off
+    steering: f32,
+    /// Boosts on straights
+    boost: bool,
+    /// Up vector y below which the car backs off the throttle to stay on its wheels
+    tilt_limit: f32,
+}
+
+impl AiDifficulty {
+    fn tuning(&self) -> DifficultyTuning {
+        match self {
+            Self::Easy => DifficultyTuning {
+                top_speed: 30.0,
+                corner_speed: 0.5,
+                lookahead: 25.0,
+                steering: 1.5,
+                boost: false,
+                tilt_limit: 0.9,
+            },
+            Self::Medium => DifficultyTuning {
+                top_speed: 42.0,
+                corner_speed: 0.6,
+                lookahead: 35.0,
+                steering: 2.0,
+                boost: false,
+                tilt_limit: 0.85,
+            },
+            Self::Hard => DifficultyTuning {
+                top_speed: 55.0,
+                corner_speed: 0.7,
+                lookahead: 45.0,
+                steering: 2.5,
+                boost: true,
+                tilt_limit: 0.8,
+            },
+        }
+    }
+}
+
+/// Which AI cars line up at the start, one per entry. None unless asked for
+#[derive(Resource, Debug, Clone, Default)]
+pub struct AiSettings {
+    pub drivers: Vec<AiDifficulty>,
+}
+
+impl AiSettings {
+    /// `--rivals` lines up one of each difficulty, `--rivals easy,hard` picks them
+    fn from_args() -> Self {
+        let args: Vec<String> = std::env::args().collect();
+        let Some(index) = args.iter().position(|arg| arg == "--rivals") else {
+            return Self::default();
+        };
+
+        let all = vec![AiDifficulty::Easy, AiDifficulty::Medium, AiDifficulty::Hard];
+        let drivers = match args.get(index + 1).filter(|list| !list.starts_with("--")) {
+            Some(list) => list
+                .split(',')
+                .filter_map(|name| match name.trim().to_ascii_lowercase().as_str() {
+                    "easy" => Some(AiDifficulty::Easy),
+                    "medium" => Some(AiDifficulty::Medium),
+                    "hard" => Some(AiDifficulty::Hard),
+                    other => {
+                        bevy::log::warn!("Unknown rival difficulty {:?}, expected easy, medium or hard", other);
+                        None
+                    }
+                })
+                .collect(),
+            None => all,
+        };
+        Self { drivers }
+    }
+
+    /// Start position of each AI car, in pairs either side of and behind the player
+    pub fn grid(&self, player_start: Vec3) -> impl Iterator<Item = (AiDifficulty, Vec3)> + '_ {
+        self.drivers.iter().enumerate().map(move |(i, difficulty)| {
+            let side = if i % 2 == 0 { 10.0 } else { -10.0 };
+            let row = (i / 2) as f32 * 14.0;
+            (*difficulty, player_start + Vec3::new(-row, 0.0, side))
+        })
+    }
+}
+
+/// Drives a car along the racing line by filling in its CarMovements, in place of player input
+#[derive(Component, Debug)]
+pub struct AiDriver {
+    pub difficulty: AiDifficulty,
+    /// Position along the racing line, in control points, None until the car has been placed on it
+    progress: Option<f32>,
+    wedged_time: f32,
+    reversing: f32,
+}
+
+impl AiDriver {
+    pub fn new(difficulty: AiDifficulty) -> Self {
+        Self {
+            difficulty,
+            progress: None,
+            wedged_time: 0.0,
+            reversing: 0.0,
+        }
+    }
+}
+
+/// A closed Catmull-Rom spline over the dunes, in the xz plane
+#[derive(Resource, Debug)]
+pub struct RacingLine {
+    points: Vec<Vec2>,
+}
+
+impl Default for RacingLine {
+    fn default() -> Self {
+        // A loop through the open desert, starting at the car's start heading down +x
+        Self {
+            points: vec![
+                Vec2::new(-700.0, 0.0),
+                Vec2::new(-200.0, 150.0),
+                Vec2::new(300.0, 600.0),
+                Vec2::new(700.0, 1300.0),
+                Vec2::new(200.0, 1800.0),
+                Vec2::new(-700.0, 1700.0),
+                Vec2::new(-1500.0, 1200.0),
+                Vec2::new(-1800.0, 400.0),
+                Vec2::new(-1400.0, -300.0),
+                Vec2::new(-1000.0, -150.0),
+            ],
+        }
+    }
+}
+
+impl RacingLine {
+    fn point(&self, index: i32) -> Vec2 {
+        self.points[index.rem_euclid(self.points.len() as i32) as usize]
+    }
+
+    /// How many control points around the loop, the range of `t` before it wraps
+    pub fn control_points(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Point on the line at `t` control points along, wrapping around the loop
+    pub fn sample(&self, t: f32) -> Vec2 {
+        let i = t.floor() as i32;
+        let f = t - t.floor();
+        let (p0, p1, p2, p3) = (self.point(i - 1), self.point(i), self.point(i + 1), self.point(i + 2));
+        0.5 * (2.0 * p1
+            + (p2 - p0) * f
+            + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * f * f
+            + (3.0 * p1 - p0 - 3.0 * p2 + p3) * f * f * f)
+    }
+
+    pub fn tangent(&self, t: f32) -> Vec2 {
+        (self.sample(t + 0.01) - self.sample(t)).normalize_or_zero()
+    }
+
+    /// Parameter `distance` meters further along the line from `t`
+    fn advance(&self, t: f32, distance: f32) -> f32 {
+        let mut t = t;
+        let mut travelled = 0.0;
+        let mut previous = self.sample(t);
+        while travelled < distance {
+            t += 0.01;
+            let next = self.sample(t);
+            travelled += previous.distance(next);
+            previous = next;
+        }
+        t
+    }
+
+    /// Closest point to `position`, searching a little behind and a good way ahead of `from`
+    /// so the car doesn't snap to a different part of the loop that happens to pass nearby
+    fn closest(&self, position: Vec2, from: f32) -> f32 {
+        (-20..=100)
+            .map(|step| from + step as f32 * 0.01)
+            .min_by(|a, b| {
+                let a = self.sample(*a).distance_squared(position);
+                let b = self.sample(*b).distance_squared(position);
+                a.total_cmp(&b)
+            })
+            .unwrap_or(from)
+            .rem_euclid(self.points.len() as f32)
+    }
+
+    /// Nearest point anywhere on the loop, for cars placed without any progress yet
+    fn nearest(&self, position: Vec2) -> f32 {
+        (0..self.points.len() * 20)
+            .map(|step| step as f32 * 0.05)
+            .min_by(|a, b| {
+                let a = self.sample(*a).distance_squared(position);
+                let b = self.sample(*b).distance_squared(position);
+                a.total_cmp(&b)
+            })
+            .unwrap_or(0.0)
+    }
+}
+
+/// Signed angle (radians) to turn from `from` to `to` around +y, positive is a left turn
+fn heading_error(from: Vec2, to: Vec2) -> f32 {
+    // The car's xz plane seen from above, turning left swings +x towards -z
+    (-(from.x * to.y - from.y * to.x)).atan2(from.dot(to))
+}
+
+fn drive_ai_cars(
+    time: Res<Time>,
+    racing_line: Res<RacingLine>,
+    read_rapier_context: ReadRapierContext,
+    mut car_query: Query<(Entity, &mut AiDriver, &mut CarMovements, &Transform, &Velocity, &CollidingEntities)>,
+    sensor_query: Query<(), With<Sensor>>,
+) {
+    let Ok(rapier_context) = read_rapier_context.single() else {
+        return;
+    };
+
+    for (car_entity, mut driver, mut movements, transform, velocity, colliding_entities) in car_query.iter_mut() {
+        let tuning = driver.difficulty.tuning();
+        let position = transform.translation;
+        let forward = (transform.rotation * Vec3::X).xz().normalize_or_zero();
+        let speed = velocity.linvel.xz().length();
+
+        // Fresh cars look for their spot on the line from scratch
+        let progress = match driver.progress {
+            Some(progress) => racing_line.closest(position.xz(), progress),
+            None => racing_line.nearest(position.xz()),
+        };
+        driver.progress = Some(progress);
+
+        // Steer for a point ahead on the line, further ahead the faster the car goes
+        let lookahead = tuning.lookahead + speed * 0.5;
+        let target = racing_line.sample(racing_line.advance(progress, lookahead));
+        let to_target = (target - position.xz()).normalize_or_zero();
+        let mut steer = (heading_error(forward, to_target) * tuning.steering).clamp(-1.0, 1.0);
+
+        // Slow down for corners by how far the line turns over the next stretch
+        let corner_start = racing_line.tangent(progress);
+        let corner_end = racing_line.tangent(racing_line.advance(progress, lookahead * 2.0));
+        let corner = (heading_error(corner_start, corner_end).abs() / std::f32::consts::FRAC_PI_2).min(1.0);
+        let mut target_speed = tuning.top_speed * (1.0 - corner * (1.0 - tuning.corner_speed));
+
+        // Probe the ground ahead: no ground means the edge of the map, steep ground a dune face
+        let filter = QueryFilter::default().exclude_rigid_body(car_entity).exclude_sensors();
+        let probe = position + Vec3::new(forward.x, 0.0, forward.y) * PROBE_DISTANCE + Vec3::Y * 50.0;
+        match rapier_context.cast_ray_and_get_normal(probe, Vec3::NEG_Y, 150.0, true, filter) {
+            None => target_speed = 0.0,
+            Some((_entity, hit)) if hit.normal.y < STEEP_SLOPE => target_speed *= 0.5,
+            _ => {}
+        }
+
+        // Leaning over, ease off and steer down the slope to land back on four wheels
+        let car_up = transform.rotation * Vec3::Y;
+        let tilted = car_up.y < tuning.tilt_limit;
+        if tilted {
+            target_speed *= 0.3;
+            let lean = car_up.dot(transform.rotation * Vec3::Z);
+            steer = if lean > 0.0 { -1.0 } else { 1.0 };
+        }
+
+        // Stuck nose first into a dune, back out with the wheels turned the other way
+        if driver.reversing > 0.0 {
+            driver.reversing -= time.delta_secs();
+            movements.0.push(CarMovement::PushBackward(PUSH_FACTOR));
+            steer = -steer;
+        } else {
+            if speed < WEDGED_SPEED && on_ground(colliding_entities, &sensor_query) && target_speed > 0.0 {
+                driver.wedged_time += time.delta_secs();
+            } else {
+                driver.wedged_time = 0.0;
+            }
+            if driver.wedged_time > WEDGED_SECONDS {
+                driver.wedged_time = 0.0;
+                driver.reversing = REVERSE_SECONDS;
+            }
+
+            let boost = tuning.boost && corner < 0.1 && !tilted;
+            let push = if boost { PUSH_FACTOR + BOOST_FACTOR } else { PUSH_FACTOR };
+            if speed < target_speed {
+                // Ease in near the target speed rather than flooring it and lifting off
+                let throttle = ((target_speed - speed) / 10.0).clamp(0.2, 1.0);
+                movements.0.push(CarMovement::PushForward(push * throttle));
+            } else if speed > target_speed + 10.0 {
+                movements.0.push(CarMovement::PushBackward(PUSH_FACTOR * 0.5));
+            }
+        }
+
+        if steer > 0.0 {
+            movements.0.push(CarMovement::TurnLeft(TURN_FACTOR * steer));
+        } else if steer < 0.0 {
+            movements.0.push(CarMovement::TurnRight(TURN_FACTOR * -steer));
+        }
+    }
+}
+
+/// AI cars that flip or fall off get dropped back onto the racing line, the race carries on
+fn recover_ai_cars(
+    mut car_out: MessageReader<CarOut>,
+    racing_line: Res<RacingLine>,
+    read_rapier_context: ReadRapierContext,
+    mut car_query: Query<(&mut AiDriver, &mut Transform, &mut Velocity, &mut StuckTimer)>,
+) {
+    let Ok(rapier_context) = read_rapier_context.single() else {
+        return;
+    };
+
+    for out in car_out.read() {
+        let Ok((mut driver, mut transform, mut velocity, mut stuck_timer)) = car_query.get_mut(out.car) else {
+            continue;
+        };
+
+        let progress = driver.progress.unwrap_or_else(|| racing_line.nearest(transform.translation.xz()));
+        let point = racing_line.sample(progress);
+        let tangent = racing_line.tangent(progress);
+        // Find the ground under the line, skipping the car itself in case it's lying there
+        let filter = QueryFilter::default().exclude_rigid_body(out.car).exclude_sensors();
+        let ground = rapier_context
+            .cast_ray(Vec3::new(point.x, 500.0, point.y), Vec3::NEG_Y, 1000.0, true, filter)
+            .map(|(_entity, distance)| 500.0 - distance)
+            .unwrap_or(transform.translation.y.max(0.0));
+
+        bevy::log::info!("AI car out ({:?}), putting it back on the racing line", out.reason);
+        transform.translation = Vec3::new(point.x, ground + RECOVERY_HEIGHT, point.y);
+        // Face along the line, the car's forward is local +x
+        transform.rotation = Quat::from_rotation_y((-tangent.y).atan2(tangent.x));
+        velocity.linvel = Vec3::ZERO;
+        velocity.angvel = Vec3::ZERO;
+        stuck_timer.reset();
+        driver.wedged_time = 0.0;
+        driver.reversing = 0.0;
+    }
+}
+
+fn reset_ai_drivers(mut driver_query: Query<&mut AiDriver>) {
+    for mut driver in driver_query.iter_mut() {
+        *driver = AiDriver::new(driver.difficulty);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square() -> RacingLine {
+        RacingLine {
+            points: vec![
+                Vec2::new(0.0, 0.0),
+                Vec2::new(100.0, 0.0),
+                Vec2::new(100.0, 100.0),
+                Vec2::new(0.0, 100.0),
+            ],
+        }
+    }
+
+    #[test]
+    fn sample_passes_through_the_control_points() {
+        let line = square();
+        for (index, point) in line.points.iter().enumerate() {
+            assert!(line.sample(index as f32).distance(*point) < 1e-3);
+        }
+    }
+
+    #[test]
+    fn sample_wraps_around_the_loop() {
+        let line = square();
+        let laps = line.control_points() as f32;
+        for t in [0.0, 0.25, 1.5, 3.75] {
+            assert!(line.sample(t).distance(line.sample(t + laps)) < 1e-3);
+            assert!(line.sample(t).distance(line.sample(t - laps)) < 1e-3);
+        }
+    }
+
+    #[test]
+    fn sample_is_continuous_across_segments() {
+        let line = square();
+        for index in 0..line.control_points() {
+            let t = index as f32;
+            assert!(line.sample(t - 0.001).distance(line.sample(t + 0.001)) < 1.0);
+        }
+    }
+
+    #[test]
+    fn straight_points_give_a_straight_line() {
+        let line = RacingLine {
+            points: (0..6).map(|i| Vec2::new(i as f32 * 10.0, 0.0)).collect(),
+        };
+        // Away from the wrap, evenly spaced points in a row interpolate linearly
+        assert!(line.sample(2.5).distance(Vec2::new(25.0, 0.0)) < 1e-3);
+        assert!(line.tangent(2.5).distance(Vec2::X) < 1e-3);
+    }
+
+    #[test]
+    fn advance_and_closest_find_their_way_back() {
+        let line = square();
+        let t = line.advance(0.5, 30.0);
+        assert!(t > 0.5);
+        let travelled = line.sample(0.5).distance(line.sample(t));
+        assert!((travelled - 30.0).abs() < 2.0);
+        assert!((line.closest(line.sample(t), 0.5) - t).abs() < 0.02);
+        assert!((line.nearest(Vec2::new(100.0, 0.0)) - 1.0).abs() < 0.05);
+    }
+}
@@ -6,8 +6,9 @@ use bevy_rapier3d::na::clamp;
 use leafwing_input_manager::prelude::*;
 use smooth_bevy_cameras::{LookAngles, LookTransform, LookTransformBundle, Smoother};
 
-use crate::car::{Car, CAR_START_POSITION};
+use crate::car::{player_start, Car, PlayerCar};
 use crate::input::CarAction;
+use crate::split_screen::SplitScreen;
 
 // Camera offset from car start position - edit this to change initial camera position
 pub const CAMERA_OFFSET_FROM_CAR: Vec3 = Vec3::new(-32.0, 16.0, -8.0);
@@ -15,40 +16,96 @@ pub const CAMERA_OFFSET_FROM_CAR: Vec3 = Vec3::new(-32.0, 16.0, -8.0);
 #[derive(Component)]
 pub struct CameraNeedsActivation;
 
+/// The car a camera follows, its player's own car until they switch
+#[derive(Component, Debug, Clone, Copy)]
+pub struct CameraTarget(pub Entity);
+
+/// Which player a camera belongs to, their controls orbit it
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerCamera(pub usize);
+
 pub fn setup(
     mut commands: Commands,
     camera_query: Query<Entity, With<Camera3d>>,
+    split_screen: Res<SplitScreen>,
 ) {
     // Remove any existing cameras (like the loading camera) before spawning the game camera
     for entity in camera_query.iter() {
         commands.entity(entity).despawn();
     }
     
-    // Spawn the game camera
-    commands
-        .spawn((
-            Camera3d::default(),
-            LookTransformBundle {
-                transform: LookTransform {
-                    eye: CAR_START_POSITION + CAMERA_OFFSET_FROM_CAR, 
-                    target: CAR_START_POSITION,
-                    up: Vec3::Y,
+    // Spawn a game camera per player, split screen sets up their viewports
+    for player in 0..split_screen.players {
+        let start = player_start(player);
+        commands
+            .spawn((
+                Camera3d::default(),
+                Camera {
+                    order: player as isize,
+                    ..default()
+                },
+                LookTransformBundle {
+                    transform: LookTransform {
+                        eye: start + CAMERA_OFFSET_FROM_CAR, 
+                        target: start,
+                        up: Vec3::Y,
+                    },
+                    smoother: Smoother::new(0.9),
                 },
-                smoother: Smoother::new(0.9),
-            },
-            CameraNeedsActivation,
-        ));
+                CameraNeedsActivation,
+                PlayerCamera(player),
+            ));
+    }
 }
 
 type CarQueryFilter = (With<Car>, Without<Camera>);
 
+/// Point cameras without a target at their player's car
+pub fn target_player_car(
+    mut commands: Commands,
+    camera_query: Query<(Entity, &PlayerCamera), (With<Camera3d>, Without<CameraTarget>)>,
+    player_query: Query<(Entity, &PlayerCar)>,
+) {
+    for (entity, camera) in camera_query.iter() {
+        if let Some((car, _)) = player_query.iter().find(|(_, player)| player.0 == camera.0) {
+            commands.entity(entity).insert(CameraTarget(car));
+        }
+    }
+}
+
+/// Switch a player's camera to the next car in the world, to watch the other cars drive
+pub fn cycle_camera_target(
+    mut camera_query: Query<(&mut CameraTarget, &PlayerCamera)>,
+    car_query: Query<Entity, CarQueryFilter>,
+    player_query: Query<(&PlayerCar, &ActionState<CarAction>)>,
+) {
+    // Entities sort in spawn order, so the cycle goes players first then the AI cars
+    let mut cars: Vec<Entity> = car_query.iter().collect();
+    cars.sort();
+
+    for (mut target, camera) in camera_query.iter_mut() {
+        let pressed = player_query
+            .iter()
+            .any(|(player, action_state)| player.0 == camera.0 && action_state.just_pressed(&CarAction::CycleCamera));
+        if !pressed {
+            continue;
+        }
+
+        let current = cars.iter().position(|car| *car == target.0).unwrap_or(0);
+        if let Some(next) = cars.get((current + 1) % cars.len().max(1)) {
+            target.0 = *next;
+        }
+    }
+}
+
 pub fn activate_camera_on_input(
     mut commands: Commands,
     keys: Res<ButtonInput<KeyCode>>,
+    touches: Res<Touches>,
     camera_query: Query<Entity, (With<Camera3d>, With<CameraNeedsActivation>)>,
 ) {
-    // If any key is pressed, remove the activation component to enable normal follow behavior
-    if keys.get_pressed().next().is_some() {
+    // If any key is pressed or the screen touched, remove the activation component to enable normal follow behavior
+    if keys.get_pressed().next().is_some() || touches.iter().next().is_some() {
         for entity in camera_query.iter() {
             commands.entity(entity).remove::<CameraNeedsActivation>();
         }
@@ -56,71 +113,78 @@ pub fn activate_camera_on_input(
 }
 
 pub fn look_and_orbit(
-    mut look_query: Query<&mut LookTransform>,
+    mut look_query: Query<(&mut LookTransform, &CameraTarget, &PlayerCamera)>,
     keys: Res<ButtonInput<KeyCode>>,
-    car_query: Query<(&GlobalTransform, &Transform, &ActionState<CarAction>), CarQueryFilter>,
+    car_query: Query<&Transform, CarQueryFilter>,
+    player_query: Query<(&PlayerCar, &ActionState<CarAction>)>,
     camera_activation_query: Query<(), With<CameraNeedsActivation>>,
 ) {
     // Only apply follow logic if camera has been activated (key pressed)
     let _is_activated = camera_activation_query.is_empty();
     
-    if let Ok(mut look_transform) = look_query.single_mut()
-        && let Ok((_global_transform, car_transform, action_state)) = car_query.single() {
-            let mut orbit = false;
-            let orbit_factor = 2.0f32.to_radians(); // Camera orbit sensitivity
+    for (mut look_transform, camera_target, camera) in look_query.iter_mut() {
+        let Ok(car_transform) = car_query.get(camera_target.0) else {
+            continue;
+        };
+        let mut orbit = false;
+        let orbit_factor = 2.0f32.to_radians(); // Camera orbit sensitivity
 
-            let mut angles = LookAngles::from_vector(-look_transform.look_direction().unwrap());
-            look_transform.target = car_transform.translation;
+        let mut angles = LookAngles::from_vector(-look_transform.look_direction().unwrap());
+        look_transform.target = car_transform.translation;
 
-            // Keyboard camera controls
-            if keys.pressed(KeyCode::KeyW) {
-                angles.add_pitch(orbit_factor);
-                orbit = true;
-            }
-            if keys.pressed(KeyCode::KeyS) {
-                angles.add_pitch(-orbit_factor);
-                orbit = true;
-            }
+        // Keyboard camera controls, the keyboard belongs to the first player
+        let keys_pressed = |key: KeyCode| camera.0 == 0 && keys.pressed(key);
+        if keys_pressed(KeyCode::KeyW) {
+            angles.add_pitch(orbit_factor);
+            orbit = true;
+        }
+        if keys_pressed(KeyCode::KeyS) {
+            angles.add_pitch(-orbit_factor);
+            orbit = true;
+        }
 
-            if keys.pressed(KeyCode::KeyD) {
-                angles.add_yaw(orbit_factor);
-                orbit = true;
-            }
+        if keys_pressed(KeyCode::KeyD) {
+            angles.add_yaw(orbit_factor);
+            orbit = true;
+        }
 
-            if keys.pressed(KeyCode::KeyA) {
-                angles.add_yaw(-orbit_factor);
-                orbit = true;
-            }
-            
-            // Gamepad left stick for camera control (using leafwing-input-manager)
+        if keys_pressed(KeyCode::KeyA) {
+            angles.add_yaw(-orbit_factor);
+            orbit = true;
+        }
+        
+        // Gamepad left stick for camera control (using leafwing-input-manager)
+        // The followed car may not be the player's own, the camera's player orbits it either way
+        for (_, action_state) in player_query.iter().filter(|(player, _)| player.0 == camera.0) {
             let camera_axis = action_state.axis_pair(&CarAction::CameraOrbit);
             if camera_axis.length_squared() > 0.01 {
                 angles.add_yaw(camera_axis.x * orbit_factor);
                 angles.add_pitch(-camera_axis.y * orbit_factor); // Invert Y for camera
                 orbit = true;
             }
+        }
 
-            // follow eye
-            if orbit {
-                look_transform.eye =
-                    look_transform.target + 1.0 * look_transform.radius() * angles.unit_vector();
-            }
+        // follow eye
+        if orbit {
+            look_transform.eye =
+                look_transform.target + 1.0 * look_transform.radius() * angles.unit_vector();
+        }
+
+        // Simple clamping-based follow (like the old implementation at 9ff6995)
+        // This keeps the camera in a good position without complex distance adjustments
+        look_transform.eye.x = clamp(
+            look_transform.eye.x,
+            car_transform.translation.x - 40.0,
+            car_transform.translation.x + 40.0,
+        );
+
+        look_transform.eye.z = clamp(
+            look_transform.eye.z,
+            car_transform.translation.z - 40.0,
+            car_transform.translation.z + 40.0,
+        );
 
-            // Simple clamping-based follow (like the old implementation at 9ff6995)
-            // This keeps the camera in a good position without complex distance adjustments
-            look_transform.eye.x = clamp(
-                look_transform.eye.x,
-                car_transform.translation.x - 40.0,
-                car_transform.translation.x + 40.0,
-            );
-
-            look_transform.eye.z = clamp(
-                look_transform.eye.z,
-                car_transform.translation.z - 40.0,
-                car_transform.translation.z + 40.0,
-            );
-
-            // Always keep camera at a fixed height above the car (like old implementation)
-            look_transform.eye.y = car_transform.translation.y + 10.0;
+        // Always keep camera at a fixed height above the car (like old implementation)
+        look_transform.eye.y = car_transform.translation.y + 10.0;
     }
 }
@@ -1,6 +1,6 @@
-use crate::{camera::{look_and_orbit, activate_camera_on_input, CameraNeedsActivation, CAMERA_OFFSET_FROM_CAR}, input::{get_car_movement, CarAction}, movement::apply_movement, state::GameState};
+use crate::{camera::{look_and_orbit, activate_camera_on_input, cycle_camera_target, target_player_car, CameraNeedsActivation, CameraTarget, PlayerCamera, CAMERA_OFFSET_FROM_CAR}, input::{get_car_movement, CarAction}, impacts::on_ground, movement::apply_movement, run::{format_duration, record_run_ended, LastRun, RunEndReason, RunEnded, RunStats}, state::GameState};
 use bevy::prelude::*;
-use bevy_rapier3d::prelude::{Velocity, CollidingEntities};
+use bevy_rapier3d::prelude::{Velocity, CollidingEntities, Sensor};
 use leafwing_input_manager::prelude::ActionState;
 use smooth_bevy_cameras::LookTransform;
 
@@ -8,16 +8,21 @@ pub struct CarPlugin;
 
 impl Plugin for CarPlugin {
     fn build(&self, app: &mut App) {
+        app.add_message::<CarOut>().add_message::<CarRecovered>();
         app.add_systems(
             Update,
             (
                 activate_camera_on_input,
                 get_car_movement,
                 apply_movement.after(get_car_movement),
-                look_and_orbit.after(apply_movement),
+                target_player_car,
+                cycle_camera_target.after(target_player_car),
+                look_and_orbit.after(apply_movement).after(cycle_camera_target),
                 // Run game over checks after movement to ensure car state is updated
                 check_stuck.after(apply_movement),
                 check_game_over.after(apply_movement),
+                end_run_when_player_out.after(check_stuck).after(check_game_over),
+                record_run_ended.after(end_run_when_player_out),
             )
                 .run_if(in_state(GameState::Running)),
         )
@@ -54,16 +59,64 @@ pub struct StuckTimer {
     reset_grace_period: f32, // Grace period after reset to prevent immediate game over
 }
 
+impl StuckTimer {
+    /// Start over with a grace period, for a car that was just put back on its wheels
+    pub fn reset(&mut self) {
+        self.reset_with_grace(0.5);
+    }
+
+    /// Start over with a longer grace period, the car can't go out or take damage until it ends
+    pub fn reset_with_grace(&mut self, grace_period: f32) {
+        self.stuck_duration = 0.0;
+        self.reset_grace_period = grace_period;
+    }
+
+    pub fn invulnerable(&self) -> bool {
+        self.reset_grace_period > 0.0
+    }
+}
+
+/// A car driven by a player, numbered from 0. Cars without one are AI or scenery
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerCar(pub usize);
+
+/// A player whose run has ended while others are still driving, their car stops taking input
+#[derive(Component, Debug)]
+pub struct PlayerOut;
+
+/// Where a car goes back to when the game restarts
+#[derive(Component, Debug, Clone, Copy)]
+pub struct SpawnPoint(pub Transform);
+
+/// A car was put back on its wheels somewhere else, after going out or with the reset button,
+/// its run carries on
+#[derive(Message, Debug, Clone, Copy)]
+pub struct CarRecovered {
+    pub car: Entity,
+}
+
+/// Per-car game over: a car flipped over or fell off the map. Ends the run for a player,
+/// AI cars get put back on their racing line instead
+#[derive(Message, Debug, Clone, Copy)]
+pub struct CarOut {
+    pub car: Entity,
+    pub reason: RunEndReason,
+}
+
 pub fn spawn_controls_text(mut commands: Commands, asset_server: Res<AssetServer>) {
     let controls_text = "
 drive
 ----------
 ↑ ↓ ← →
 space (boost)
+shift (handbrake)
+h (headlights)
+esc (settings)
 
 look
 ----------
-w a s d";
+w a s d
+c (switch car)";
 
     let font_handle = asset_server.load("font/NotoSansMono-Bold.ttf");
 
@@ -92,17 +145,18 @@ w a s d";
         });
 }
 
-fn check_stuck(
-    mut car_query: Query<(&Transform, &mut StuckTimer, &CollidingEntities), With<Car>>,
+pub fn check_stuck(
+    mut car_query: Query<(Entity, &Transform, &mut StuckTimer, &CollidingEntities), With<Car>>,
+    sensor_query: Query<(), With<Sensor>>,
     time: Res<Time>,
-    mut next_state: ResMut<NextState<GameState>>,
+    mut car_out: MessageWriter<CarOut>,
 ) {
-    if let Ok((transform, mut stuck_timer, colliding_entities)) = car_query.single_mut() {
+    for (car, transform, mut stuck_timer, colliding_entities) in car_query.iter_mut() {
         // Decrease grace period after reset
         if stuck_timer.reset_grace_period > 0.0 {
             stuck_timer.reset_grace_period = (stuck_timer.reset_grace_period - time.delta_secs()).max(0.0);
             // Don't check for game over during grace period
-            return;
+            continue;
         }
         
         // Check if car is on its side or upside down
@@ -117,15 +171,16 @@ fn check_stuck(
         let is_upside_down_or_on_side = dot_product < 0.5;
         
         // Check if car is touching the ground (any part of the car is colliding)
-        let is_touching_ground = !colliding_entities.is_empty();
+        let is_touching_ground = on_ground(colliding_entities, &sensor_query);
         
         // If car is in bad orientation AND touching ground, increment timer
         if is_upside_down_or_on_side && is_touching_ground {
             stuck_timer.stuck_duration += time.delta_secs();
             
-            // If in bad orientation for more than 1/4 second, trigger game over
+            // If in bad orientation for more than 1/4 second, the car is out
             if stuck_timer.stuck_duration >= 0.25 {
-                next_state.set(GameState::GameOver);
+                stuck_timer.stuck_duration = 0.0;
+                car_out.write(CarOut { car, reason: RunEndReason::Flipped });
             }
         } else {
             // Reset timer if conditions aren't met (car is upright or not touching ground)
@@ -134,23 +189,61 @@ fn check_stuck(
     }
 }
 
-fn check_game_over(
-    car_query: Query<&Transform, With<Car>>,
-    mut next_state: ResMut<NextState<GameState>>,
+pub fn check_game_over(
+    car_query: Query<(Entity, &Transform), With<Car>>,
+    mut car_out: MessageWriter<CarOut>,
 ) {
-    if let Ok(car_transform) = car_query.single() {
+    for (car, car_transform) in car_query.iter() {
         // Check if car has fallen off the map (Y position too low)
         if car_transform.translation.y < -200.0 {
-            next_state.set(GameState::GameOver);
+            car_out.write(CarOut { car, reason: RunEndReason::FellOff });
         }
     }
 }
 
+/// A player's car going out ends their run, the game is over once every player is out
+pub fn end_run_when_player_out(
+    mut commands: Commands,
+    mut car_out: MessageReader<CarOut>,
+    mut car_recovered: MessageReader<CarRecovered>,
+    player_query: Query<(Entity, &PlayerCar, &RunStats, Has<PlayerOut>)>,
+    mut run_ended: MessageWriter<RunEnded>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let recovered: Vec<Entity> = car_recovered.read().map(|recovered| recovered.car).collect();
+    let mut newly_out = Vec::new();
+    for out in car_out.read() {
+        // Flipping and falling in the same frame should still only end a run once
+        if newly_out.contains(&out.car) || recovered.contains(&out.car) {
+            continue;
+        }
+        if let Ok((car, player, run_stats, false)) = player_query.get(out.car) {
+            run_ended.write(run_stats.finish(player.0, out.reason));
+            commands.entity(car).insert(PlayerOut);
+            newly_out.push(car);
+        }
+    }
+
+    let still_driving = player_query
+        .iter()
+        .any(|(car, _, _, already_out)| !already_out && !newly_out.contains(&car));
+    if !newly_out.is_empty() && !still_driving {
+        next_state.set(GameState::GameOver);
+    }
+}
+
+pub fn reset_car(transform: &mut Transform, velocity: &mut Velocity, stuck_timer: &mut StuckTimer, spawn_point: &SpawnPoint) {
+    *transform = spawn_point.0;
+    velocity.linvel = Vec3::ZERO;
+    velocity.angvel = Vec3::ZERO;
+    stuck_timer.reset(); // Grace period so the car isn't out again straight away
+}
+
 fn stop_car_momentum(
     mut car_query: Query<&mut Velocity, With<Car>>,
 ) {
     // Zero horizontal velocity and angular velocity, but ensure car falls
-    if let Ok(mut velocity) = car_query.single_mut() {
+    for mut velocity in car_query.iter_mut() {
         velocity.linvel.x = 0.0;
         velocity.linvel.z = 0.0;
         velocity.angvel = Vec3::ZERO; // Stop spinning
@@ -164,10 +257,11 @@ fn stop_car_momentum(
 
 fn freeze_car_on_ground(
     mut car_query: Query<(&mut Velocity, &CollidingEntities), With<Car>>,
+    sensor_query: Query<(), With<Sensor>>,
 ) {
     // Once car hits the ground (is colliding) and has low velocity, fully freeze it
-    if let Ok((mut velocity, colliding_entities)) = car_query.single_mut() {
-        if !colliding_entities.is_empty() {
+    for (mut velocity, colliding_entities) in car_query.iter_mut() {
+        if on_ground(colliding_entities, &sensor_query) {
             // Car is on ground - only freeze if velocity is very low (essentially stopped)
             if velocity.linvel.length() < 1.0 {
                 velocity.linvel = Vec3::ZERO;
@@ -181,23 +275,32 @@ fn freeze_car_on_ground(
     }
 }
 
+type ResetCarQuery<'w, 's> = Query<'w, 's, (&'static mut Transform, &'static mut Velocity, &'static mut StuckTimer, &'static SpawnPoint), With<Car>>;
+
 fn reset_car_on_exit_game_over(
-    mut car_query: Query<(&mut Transform, &mut Velocity, &mut StuckTimer), With<Car>>,
+    mut commands: Commands,
+    mut car_query: ResetCarQuery,
+    out_query: Query<Entity, With<PlayerOut>>,
 ) {
-    // Ensure car is reset when exiting game over state (as a backup to button handler)
-    // This runs when transitioning from GameOver to Running, ensuring car is always reset
-    if let Ok((mut transform, mut velocity, mut stuck_timer)) = car_query.single_mut() {
-        transform.translation = CAR_START_POSITION;
-        transform.rotation = Quat::IDENTITY;
-        velocity.linvel = Vec3::ZERO;
-        velocity.angvel = Vec3::ZERO;
-        stuck_timer.stuck_duration = 0.0;
-        stuck_timer.reset_grace_period = 0.5;
+    // Ensure cars are reset when exiting game over state (as a backup to button handler)
+    // This runs when transitioning from GameOver to Running, ensuring cars are always reset
+    for (mut transform, mut velocity, mut stuck_timer, spawn_point) in car_query.iter_mut() {
+        reset_car(&mut transform, &mut velocity, &mut stuck_timer, spawn_point);
+    }
+    // Everyone drives again
+    for entity in out_query.iter() {
+        commands.entity(entity).remove::<PlayerOut>();
     }
 }
 
-fn spawn_game_over_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn spawn_game_over_screen(mut commands: Commands, asset_server: Res<AssetServer>, last_run: Res<LastRun>) {
     let font_handle = asset_server.load("font/NotoSansMono-Bold.ttf");
+
+    // Split screen shows each player's reason with their stats instead
+    let (title, reason) = match last_run.0.as_slice() {
+        [run] => (run.reason.title(), run.reason.description()),
+        _ => ("GAME OVER", ""),
+    };
     
     commands
         .spawn((
@@ -212,9 +315,9 @@ fn spawn_game_over_screen(mut commands: Commands, asset_server: Res<AssetServer>
             GameOverScreen,
         ))
         .with_children(|parent| {
-            // "GAME OVER" text
+            // Title with the reason the run ended
             parent.spawn((
-                Text("GAME OVER".to_string()),
+                Text(title.to_string()),
                 TextFont {
                     font: font_handle.clone(),
                     font_size: 64.,
@@ -222,6 +325,62 @@ fn spawn_game_over_screen(mut commands: Commands, asset_server: Res<AssetServer>
                 },
                 TextColor(Color::srgb(0.9, 0.9, 0.9)),
             ));
+
+            parent.spawn((
+                Text(reason.to_string()),
+                TextFont {
+                    font: font_handle.clone(),
+                    font_size: 20.,
+                    ..default()
+                },
+                TextColor(Color::srgb_u8(0xAB, 0x69, 0xE7)),
+            ));
+
+            // Run stats, side by side for each player
+            parent
+                .spawn(Node {
+                    flex_direction: FlexDirection::Row,
+                    column_gap: Val::Px(60.),
+                    ..default()
+                })
+                .with_children(|row| {
+                    for run in &last_run.0 {
+                        let heading = if last_run.0.len() > 1 {
+                            format!("\nplayer {}\n{}", run.player + 1, run.reason.title().to_lowercase())
+                        } else {
+                            String::new()
+                        };
+                        let stats_text = format!(
+                            "{}
+time        {}
+distance    {:.0} m
+top speed   {:.0} km/h
+avg speed   {:.0} km/h
+air time    {:.1} s
+boost time  {:.1} s
+pickups     {}
+",
+                            heading,
+                            format_duration(run.duration),
+                            run.distance,
+                            run.max_speed * 3.6,
+                            run.average_speed * 3.6,
+                            run.air_time,
+                            run.boost_time,
+                            run.pickups,
+                        );
+
+                        row.spawn((
+                            Text(stats_text),
+                            TextFont {
+                                font: font_handle.clone(),
+                                font_size: 20.,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.9, 0.9, 0.9)),
+                        ));
+                    }
+                });
             
             // "PLAY AGAIN?" button
             parent
@@ -248,8 +407,8 @@ fn handle_play_again_button(
     interaction_query: Query<(&Interaction, &Children), (Changed<Interaction>, With<PlayAgainButton>)>,
     mut text_color_query: Query<&mut TextColor>,
     mut next_state: ResMut<NextState<GameState>>,
-    mut car_query: Query<(&mut Transform, &mut Velocity, &mut StuckTimer), With<Car>>,
-    mut camera_query: Query<(Entity, &mut LookTransform), With<Camera3d>>,
+    mut car_query: ResetCarQuery,
+    mut camera_query: Query<(Entity, &mut LookTransform, &PlayerCamera)>,
 ) {
     for (interaction, children) in interaction_query.iter() {
         // Update text color on hover
@@ -264,25 +423,21 @@ fn handle_play_again_button(
                         *text_color = TextColor(Color::srgb(0.9, 0.9, 0.9));
                     }
                     Interaction::Pressed => {
-                        // Reset car to starting position - try to reset, but OnExit handler will ensure it happens
-                        if let Ok((mut transform, mut velocity, mut stuck_timer)) = car_query.single_mut() {
-                            transform.translation = CAR_START_POSITION;
-                            transform.rotation = Quat::IDENTITY; // Ensure car is upright
-                            velocity.linvel = Vec3::ZERO;
-                            velocity.angvel = Vec3::ZERO;
-                            stuck_timer.stuck_duration = 0.0; // Reset stuck timer to prevent immediate game over
-                            stuck_timer.reset_grace_period = 0.5; // Give 0.5 seconds grace period after reset
+                        // Reset cars to their starting positions - try to reset, but OnExit handler will ensure it happens
+                        for (mut transform, mut velocity, mut stuck_timer, spawn_point) in car_query.iter_mut() {
+                            reset_car(&mut transform, &mut velocity, &mut stuck_timer, spawn_point);
                         }
                         
                         // Always transition to Running state - the OnExit handler will ensure car is reset
                         next_state.set(GameState::Running);
                         
                         // Reset camera to far out position (behind and high), then it will smoothly zoom in when activated
-                        for (entity, mut look_transform) in camera_query.iter_mut() {
-                            look_transform.eye = CAR_START_POSITION + CAMERA_OFFSET_FROM_CAR;
-                            look_transform.target = CAR_START_POSITION;
-                            // Re-add activation component so camera needs to be activated again
-                            commands.entity(entity).insert(CameraNeedsActivation);
+                        for (entity, mut look_transform, camera) in camera_query.iter_mut() {
+                            look_transform.eye = player_start(camera.0) + CAMERA_OFFSET_FROM_CAR;
+                            look_transform.target = player_start(camera.0);
+                            // Re-add activation component so camera needs to be activated again,
+                            // and go back to following the player if it was watching another car
+                            commands.entity(entity).remove::<CameraTarget>().insert(CameraNeedsActivation);
                         }
                     }
                 }
@@ -300,30 +455,23 @@ fn cleanup_game_over_screen(mut commands: Commands, query: Query<Entity, With<Ga
 fn handle_gamepad_play_again(
     mut commands: Commands,
     mut next_state: ResMut<NextState<GameState>>,
-    mut car_query: Query<(&mut Transform, &mut Velocity, &mut StuckTimer), With<Car>>,
-    mut camera_query: Query<(Entity, &mut LookTransform), With<Camera3d>>,
-    action_state_query: Query<&ActionState<CarAction>, With<Car>>,
+    mut car_query: ResetCarQuery,
+    mut camera_query: Query<(Entity, &mut LookTransform, &PlayerCamera)>,
+    action_state_query: Query<&ActionState<CarAction>, With<PlayerCar>>,
 ) {
-    // Check if PlayAgain action is pressed (A button on Xbox controller)
-    if let Ok(action_state) = action_state_query.single() {
-        if action_state.just_pressed(&CarAction::PlayAgain) {
-            // Same logic as handle_play_again_button when pressed
-            if let Ok((mut transform, mut velocity, mut stuck_timer)) = car_query.single_mut() {
-                transform.translation = CAR_START_POSITION;
-                transform.rotation = Quat::IDENTITY;
-                velocity.linvel = Vec3::ZERO;
-                velocity.angvel = Vec3::ZERO;
-                stuck_timer.stuck_duration = 0.0;
-                stuck_timer.reset_grace_period = 0.5;
-            }
-            
-            next_state.set(GameState::Running);
-            
-            for (entity, mut look_transform) in camera_query.iter_mut() {
-                look_transform.eye = CAR_START_POSITION + CAMERA_OFFSET_FROM_CAR;
-                look_transform.target = CAR_START_POSITION;
-                commands.entity(entity).insert(CameraNeedsActivation);
-            }
+    // Check if PlayAgain action is pressed (A button on Xbox controller), by any player
+    if action_state_query.iter().any(|action_state| action_state.just_pressed(&CarAction::PlayAgain)) {
+        // Same logic as handle_play_again_button when pressed
+        for (mut transform, mut velocity, mut stuck_timer, spawn_point) in car_query.iter_mut() {
+            reset_car(&mut transform, &mut velocity, &mut stuck_timer, spawn_point);
+        }
+        
+        next_state.set(GameState::Running);
+        
+        for (entity, mut look_transform, camera) in camera_query.iter_mut() {
+            look_transform.eye = player_start(camera.0) + CAMERA_OFFSET_FROM_CAR;
+            look_transform.target = player_start(camera.0);
+            commands.entity(entity).remove::<CameraTarget>().insert(CameraNeedsActivation);
         }
     }
 }
@@ -331,8 +479,27 @@ fn handle_gamepad_play_again(
 // Car starting position - edit this to change where the car spawns/respawns
 pub const CAR_START_POSITION: Vec3 = Vec3::new(-700.0, 10.0, 0.0);
 
+/// Where each player's car starts, side by side in split screen
+pub fn player_start(player: usize) -> Vec3 {
+    CAR_START_POSITION + Vec3::new(0.0, 0.0, -20.0 * player as f32)
+}
+
+// Where each tire touches the ground in the car's local space (front left, front right, rear left, rear right)
+pub const WHEEL_OFFSETS: [Vec3; 4] = [
+    Vec3::new(2.4, -1.6, 1.9),
+    Vec3::new(2.4, -1.6, -1.9),
+    Vec3::new(-2.4, -1.6, 1.9),
+    Vec3::new(-2.4, -1.6, -1.9),
+];
+
 #[derive(Default, Component, Debug)]
 pub struct Car {
     pub thrust: Vec3,
     pub drag: Vec3,
+    /// Slip angle (radians) past which the tires start to lose lateral grip
+    pub peak_slip_angle: f32,
+    /// Fraction of lateral grip left once the tires are fully sliding
+    pub sliding_grip: f32,
+    /// Fraction of rear lateral grip left while the handbrake is held
+    pub handbrake_grip: f32,
 }
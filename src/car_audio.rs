@@ -0,0 +1,175 @@
+use bevy::prelude::*;
+use bevy_kira_audio::{AudioChannel, AudioControl, AudioInstance, AudioSource, AudioTween};
+use bevy_rapier3d::prelude::{CollidingEntities, Sensor, Velocity};
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::{
+    car::PlayerCar,
+    impacts::{on_ground, CarImpact, CarLanded},
+    input::CarAction,
+    movement::Drift,
+    settings::SfxChannel,
+    state::GameState,
+    synth,
+};
+
+pub struct CarAudioPlugin;
+
+impl Plugin for CarAudioPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Running), setup_car_audio)
+            .add_systems(
+                Update,
+                (update_engine_sound, play_car_one_shots).run_if(in_state(GameState::Running)),
+            )
+            .add_systems(OnEnter(GameState::GameOver), silence_engine);
+    }
+}
+
+// Speed (m/s) at which the engine sits at the top of its range
+const TOP_SPEED: f32 = 60.0;
+// Impacts need this much contact force before they make a sound
+const CRASH_FORCE: f32 = 150_000.0;
+
+#[derive(Resource)]
+struct CarSounds {
+    engine_instance: Handle<AudioInstance>,
+    thud: Handle<AudioSource>,
+    crash: Handle<AudioSource>,
+    squeal: Handle<AudioSource>,
+}
+
+/// Per-car audio state
+#[derive(Component, Debug, Default)]
+pub struct EngineSound {
+    /// Smoothed engine speed, 0 idle to 1 redline
+    rpm: f32,
+    was_drifting: bool,
+    crash_cooldown: f32,
+}
+
+fn setup_car_audio(
+    mut commands: Commands,
+    existing_sounds: Option<Res<CarSounds>>,
+    mut audio_sources: ResMut<Assets<AudioSource>>,
+    sfx_channel: Res<AudioChannel<SfxChannel>>,
+) {
+    // Sounds are generated once and reused across runs
+    if existing_sounds.is_some() {
+        return;
+    }
+
+    let engine = audio_sources.add(synth::audio_source(synth::engine(1)));
+    let engine_instance = sfx_channel
+        .play(engine)
+        .looped()
+        .with_volume(synth::decibels(0.0))
+        .handle();
+
+    commands.insert_resource(CarSounds {
+        engine_instance,
+        thud: audio_sources.add(synth::audio_source(synth::thud(2))),
+        crash: audio_sources.add(synth::audio_source(synth::crash(3))),
+        squeal: audio_sources.add(synth::audio_source(synth::squeal(4))),
+    });
+}
+
+fn update_engine_sound(
+    time: Res<Time>,
+    car_sounds: Option<Res<CarSounds>>,
+    mut audio_instances: ResMut<Assets<AudioInstance>>,
+    mut car_query: Query<(&mut EngineSound, &Velocity, &CollidingEntities, &ActionState<CarAction>, &PlayerCar)>,
+    sensor_query: Query<(), With<Sensor>>,
+) {
+    let Some(car_sounds) = car_sounds else {
+        return;
+    };
+
+    // There's one engine loop, it follows the first player's car
+    if let Some((mut engine_sound, velocity, colliding_entities, action_state, _)) =
+        car_query.iter_mut().find(|(.., player)| player.0 == 0)
+    {
+        let throttle = action_state.value(&CarAction::Throttle).abs() > 0.1;
+        let boost = action_state.pressed(&CarAction::Boost);
+        let airborne = !on_ground(colliding_entities, &sensor_query);
+
+        // Revs follow road speed, with a kick for throttle and boost.
+        // With the wheels off the ground there's no load, so the engine flares
+        let mut target = (velocity.linvel.length() / TOP_SPEED).min(1.0) * 0.7;
+        if throttle {
+            target += if airborne { 0.5 } else { 0.2 };
+        }
+        if boost {
+            target += 0.15;
+        }
+        let target = target.min(1.0);
+
+        // Engines spin up faster than they spin down
+        let rate = if target > engine_sound.rpm { 4.0 } else { 1.5 };
+        engine_sound.rpm += (target - engine_sound.rpm) * (rate * time.delta_secs()).min(1.0);
+
+        if let Some(instance) = audio_instances.get_mut(&car_sounds.engine_instance) {
+            let volume = 0.25 + if throttle { 0.3 } else { 0.0 } + engine_sound.rpm * 0.2;
+            instance.set_playback_rate(0.6 + engine_sound.rpm as f64 * 1.8, AudioTween::default());
+            instance.set_volume(synth::decibels(volume), AudioTween::default());
+        }
+    }
+}
+
+fn play_car_one_shots(
+    time: Res<Time>,
+    car_sounds: Option<Res<CarSounds>>,
+    sfx_channel: Res<AudioChannel<SfxChannel>>,
+    mut car_landed: MessageReader<CarLanded>,
+    mut car_impact: MessageReader<CarImpact>,
+    // Only the players' own cars, rivals and remote cars crashing across the map would be a din
+    mut car_query: Query<(&mut EngineSound, &Drift), With<PlayerCar>>,
+) {
+    let Some(car_sounds) = car_sounds else {
+        return;
+    };
+
+    for landed in car_landed.read() {
+        if !car_query.contains(landed.car) {
+            continue;
+        }
+        // Harder landings are louder and lower
+        let strength = (landed.impact_speed / 60.0).clamp(0.2, 1.0);
+        sfx_channel
+            .play(car_sounds.thud.clone())
+            .with_volume(synth::decibels(strength))
+            .with_playback_rate(1.2 - strength as f64 * 0.4);
+    }
+
+    for impact in car_impact.read() {
+        if impact.force < CRASH_FORCE {
+            continue;
+        }
+
+        // Contact forces are reported every step while touching, don't stack crunches
+        if let Ok((mut engine_sound, _)) = car_query.get_mut(impact.car)
+            && engine_sound.crash_cooldown <= 0.0
+        {
+            engine_sound.crash_cooldown = 0.4;
+            let strength = (impact.force / (CRASH_FORCE * 4.0)).clamp(0.3, 1.0);
+            sfx_channel.play(car_sounds.crash.clone()).with_volume(synth::decibels(strength));
+        }
+    }
+
+    for (mut engine_sound, drift) in car_query.iter_mut() {
+        engine_sound.crash_cooldown = (engine_sound.crash_cooldown - time.delta_secs()).max(0.0);
+
+        if drift.is_drifting && !engine_sound.was_drifting {
+            sfx_channel.play(car_sounds.squeal.clone()).with_volume(synth::decibels(0.6));
+        }
+        engine_sound.was_drifting = drift.is_drifting;
+    }
+}
+
+fn silence_engine(car_sounds: Option<Res<CarSounds>>, mut audio_instances: ResMut<Assets<AudioInstance>>) {
+    if let Some(car_sounds) = car_sounds
+        && let Some(instance) = audio_instances.get_mut(&car_sounds.engine_instance)
+    {
+        instance.set_volume(synth::decibels(0.0), AudioTween::default());
+    }
+}
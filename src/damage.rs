@@ -0,0 +1,530 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{light::NotShadowCaster, mesh::VertexAttributeValues, prelude::*};
+use bevy_rapier3d::prelude::{Collider, RigidBody, Velocity};
+
+use crate::{
+    camera::PlayerCamera,
+    car::{CarOut, PlayerCar, StuckTimer, WHEEL_OFFSETS},
+    impacts::CarImpact,
+    movement::apply_movement,
+    pickups::{PickupCollected, PickupKind},
+    rng::Rng,
+    split_screen::{spawn_player_text_huds, HudLine},
+    state::GameState,
+};
+
+/// Hard hits dent the car where they land, sap its thrust and steering, make it smoke and
+/// eventually knock a wheel off. Repair kits and a new run fix it
+pub struct DamagePlugin;
+
+impl Plugin for DamagePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Running), setup_damage_assets)
+            .add_systems(
+                Update,
+                (
+                    take_damage.before(apply_movement),
+                    repair_from_pickups,
+                    repair_recovered_cars,
+                    lose_wheels.after(take_damage),
+                    dent_car_meshes
+                        .after(take_damage)
+                        .after(lose_wheels)
+                        .after(repair_from_pickups)
+                        .after(repair_recovered_cars),
+                    emit_smoke,
+                    spawn_damage_huds,
+                    update_damage_huds.after(take_damage).after(spawn_damage_huds),
+                )
+                    .run_if(in_state(GameState::Running)),
+            )
+            // Smoke drifts off and wheels keep rolling behind the game over screen
+            .add_systems(Update, (update_smoke, expire_lost_wheels).run_if(resource_exists::<DamageAssets>))
+            .add_systems(OnExit(GameState::GameOver), (repair_all_cars, despawn_lost_wheels));
+    }
+}
+
+// Contact forces under this are everyday bumps, from here up to FULL_DAMAGE_FORCE they do more and more harm
+const DAMAGE_FORCE: f32 = 200_000.0;
+const FULL_DAMAGE_FORCE: f32 = 1_200_000.0;
+const UNDERSIDE_DAMAGE_FORCE: f32 = 600_000.0;
+// Rapier reports contact forces every step a hit lasts, this is the most one step can do
+const MAX_DAMAGE_PER_STEP: f32 = 0.12;
+// At full damage a region takes this much off what it's responsible for
+const THRUST_LOSS: f32 = 0.5;
+const STEERING_LOSS: f32 = 0.5;
+// Losing a wheel costs this on top
+const WHEEL_THRUST_LOSS: f32 = 0.2;
+const WHEEL_STEERING_LOSS: f32 = 0.35;
+const WHEEL_LOSS_DAMAGE: f32 = 0.8;
+// Dents push the body in this deep at full damage, fading out over this radius
+const MAX_DENT_DEPTH: f32 = 0.9;
+const DENT_RADIUS: f32 = 1.6;
+const SMOKE_DAMAGE: f32 = 0.35;
+const SMOKE_LIFETIME: f32 = 2.0;
+// Puffs per second at full damage
+const SMOKE_RATE: f32 = 20.0;
+const LOST_WHEEL_LIFETIME: f32 = 15.0;
+// Half extents of the car's collider, where dents land
+const BODY_EXTENTS: Vec3 = Vec3::new(3.8, 1.4, 2.2);
+// The wheels are part of the car mesh and stick out past the body's sides, anything further
+// out than this on a corner is that corner's wheel
+const WHEEL_INNER_EDGE: f32 = 1.55;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DamageRegion {
+    Front,
+    Rear,
+    Left,
+    Right,
+}
+
+impl DamageRegion {
+    pub const ALL: [DamageRegion; 4] = [DamageRegion::Front, DamageRegion::Rear, DamageRegion::Left, DamageRegion::Right];
+
+    /// Outward direction of this side in the car's local space, forward is +x and right is +z
+    fn normal(&self) -> Vec3 {
+        match self {
+            Self::Front => Vec3::X,
+            Self::Rear => Vec3::NEG_X,
+            Self::Left => Vec3::NEG_Z,
+            Self::Right => Vec3::Z,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Front => "front",
+            Self::Rear => "rear",
+            Self::Left => "left",
+            Self::Right => "right",
+        }
+    }
+}
+
+/// How battered each side of a car is, 0 untouched to 1 wrecked
+#[derive(Component, Debug, Default)]
+pub struct Damage {
+    /// Indexed by `DamageRegion as usize`
+    pub regions: [f32; 4],
+    pub wheel_lost: bool,
+    /// Hits waiting to be pressed into the mesh, car-local point and depth
+    pending_dents: Vec<(Vec3, f32)>,
+    /// The wheel that just came off, by its `WHEEL_OFFSETS` entry, waiting to be taken off the mesh
+    pending_lost_wheel: Option<Vec3>,
+    /// Set when the dents need taking back out of the mesh
+    repaired: bool,
+    smoke_accumulator: f32,
+}
+
+impl Damage {
+    pub fn total(&self) -> f32 {
+        self.regions.iter().sum::<f32>() / self.regions.len() as f32
+    }
+
+    /// Engine and drivetrain sit front and back
+    pub fn thrust_factor(&self) -> f32 {
+        let drivetrain = (self.regions[DamageRegion::Front as usize] + self.regions[DamageRegion::Rear as usize]) * 0.5;
+        let wheel = if self.wheel_lost { WHEEL_THRUST_LOSS } else { 0.0 };
+        (1.0 - drivetrain * THRUST_LOSS - wheel).max(0.1)
+    }
+
+    /// Bent suspension on either side pulls at the steering
+    pub fn steering_factor(&self) -> f32 {
+        let suspension = (self.regions[DamageRegion::Left as usize] + self.regions[DamageRegion::Right as usize]) * 0.5;
+        let wheel = if self.wheel_lost { WHEEL_STEERING_LOSS } else { 0.0 };
+        (1.0 - suspension * STEERING_LOSS - wheel).max(0.1)
+    }
+
+    fn repair(&mut self) {
+        *self = Damage {
+            repaired: true,
+            ..default()
+        };
+    }
+}
+
+/// The car's own copy of a mesh, made the first time it's dented so other cars stay smooth
+#[derive(Component, Debug)]
+struct DentedMesh {
+    original: Handle<Mesh>,
+}
+
+#[derive(Component, Debug)]
+struct Smoke {
+    velocity: Vec3,
+    age: f32,
+}
+
+#[derive(Component, Debug)]
+struct LostWheel {
+    age: f32,
+}
+
+#[derive(Resource)]
+struct DamageAssets {
+    smoke_mesh: Handle<Mesh>,
+    smoke_material: Handle<StandardMaterial>,
+    wheel_mesh: Handle<Mesh>,
+    wheel_material: Handle<StandardMaterial>,
+    rng: Rng,
+}
+
+/// How battered a player's car is, in their half of the screen when it's split
+#[derive(Component, Clone)]
+struct DamageHudText;
+
+fn setup_damage_assets(
+    mut commands: Commands,
+    existing_assets: Option<Res<DamageAssets>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    // Shared across runs, only build them once
+    if existing_assets.is_some() {
+        return;
+    }
+
+    commands.insert_resource(DamageAssets {
+        smoke_mesh: meshes.add(Sphere::new(1.0).mesh().uv(8, 6)),
+        smoke_material: materials.add(StandardMaterial {
+            base_color: Color::srgba(0.15, 0.15, 0.15, 0.5),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        }),
+        wheel_mesh: meshes.add(Cylinder::new(0.8, 0.6)),
+        wheel_material: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.08, 0.08, 0.08),
+            perceptual_roughness: 0.9,
+            ..default()
+        }),
+        rng: Rng::new(0xDA_3A6E),
+    });
+}
+
+fn take_damage(
+    mut car_impact: MessageReader<CarImpact>,
+    mut car_query: Query<(&mut Damage, &Transform, &Velocity, Option<&StuckTimer>)>,
+) {
+    for impact in car_impact.read() {
+        if impact.force < DAMAGE_FORCE {
+            continue;
+        }
+        let Ok((mut damage, transform, velocity, stuck_timer)) = car_query.get_mut(impact.car) else {
+            continue;
+        };
+        // A car that was just put back down settles in without a scratch
+        if stuck_timer.is_some_and(StuckTimer::invulnerable) {
+            continue;
+        }
+
+        let amount = ((impact.force - DAMAGE_FORCE) / (FULL_DAMAGE_FORCE - DAMAGE_FORCE)).min(1.0) * MAX_DAMAGE_PER_STEP;
+        let to_local = transform.rotation.inverse();
+        let push = to_local * impact.direction;
+
+        // Mostly vertical is a landing or a roll. The suspension soaks up ordinary landings,
+        // only big drops hurt, and the underside shares it out
+        if push.y.abs() > push.x.abs().max(push.z.abs()) {
+            if impact.force >= UNDERSIDE_DAMAGE_FORCE {
+                for region in damage.regions.iter_mut() {
+                    *region = (*region + amount * 0.25).min(1.0);
+                }
+            }
+            continue;
+        }
+
+        // The side that hit is the one the car was moving into, or failing that the one the force pushes back
+        let local_velocity = to_local * velocity.linvel;
+        let lengthwise = push.x.abs() > push.z.abs();
+        let (axis_velocity, axis_push) = if lengthwise {
+            (local_velocity.x, push.x)
+        } else {
+            (local_velocity.z, push.z)
+        };
+        let outward = if axis_velocity.abs() > 1.0 { axis_velocity.signum() } else { -axis_push.signum() };
+        let region = match (lengthwise, outward > 0.0) {
+            (true, true) => DamageRegion::Front,
+            (true, false) => DamageRegion::Rear,
+            (false, true) => DamageRegion::Right,
+            (false, false) => DamageRegion::Left,
+        };
+
+        let level = &mut damage.regions[region as usize];
+        let before = *level;
+        *level = (*level + amount).min(1.0);
+        // Dents only go as deep as the damage grew, so a wrecked side stops caving in
+        let depth = (*level - before) * MAX_DENT_DEPTH;
+        if depth > 0.0 {
+            // Dent the middle of that side, a little to one end or the other
+            let along = if lengthwise { Vec3::Z * push.z.signum() } else { Vec3::X * push.x.signum() };
+            let point = region.normal() * BODY_EXTENTS + along * 0.5;
+            damage.pending_dents.push((point, depth));
+        }
+    }
+}
+
+fn repair_from_pickups(mut pickup_collected: MessageReader<PickupCollected>, mut damage_query: Query<&mut Damage>) {
+    for collected in pickup_collected.read() {
+        if collected.kind == PickupKind::RepairKit
+            && let Ok(mut damage) = damage_query.get_mut(collected.car)
+        {
+            damage.repair();
+        }
+    }
+}
+
+/// AI and network cars are put back on the road when they go out, fixed up too
+fn repair_recovered_cars(mut car_out: MessageReader<CarOut>, mut damage_query: Query<&mut Damage, Without<PlayerCar>>) {
+    for out in car_out.read() {
+        if let Ok(mut damage) = damage_query.get_mut(out.car) {
+            damage.repair();
+        }
+    }
+}
+
+fn repair_all_cars(mut damage_query: Query<&mut Damage>) {
+    for mut damage in damage_query.iter_mut() {
+        damage.repair();
+    }
+}
+
+/// Whether a point on the car mesh, in the car's space, belongs to the wheel at this offset
+fn on_wheel(point: Vec3, offset: Vec3) -> bool {
+    point.z.abs() >= WHEEL_INNER_EDGE && point.x.signum() == offset.x.signum() && point.z.signum() == offset.z.signum()
+}
+
+/// Press pending dents into the car's meshes and take lost wheels off them, or put the originals
+/// back after a repair
+fn dent_car_meshes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut car_query: Query<(Entity, &mut Damage, &GlobalTransform)>,
+    children_query: Query<&Children>,
+    mut mesh_query: Query<(&mut Mesh3d, &GlobalTransform, Option<&DentedMesh>)>,
+) {
+    for (car, mut damage, car_transform) in car_query.iter_mut() {
+        if !damage.repaired && damage.pending_dents.is_empty() && damage.pending_lost_wheel.is_none() {
+            continue;
+        }
+        let repaired = std::mem::take(&mut damage.repaired);
+        let dents = std::mem::take(&mut damage.pending_dents);
+        let lost_wheel = damage.pending_lost_wheel.take();
+        let car_from_world = car_transform.affine().inverse();
+
+        for entity in children_query.iter_descendants(car) {
+            let Ok((mut mesh3d, mesh_transform, dented)) = mesh_query.get_mut(entity) else {
+                continue;
+            };
+
+            if repaired {
+                if let Some(dented) = dented {
+                    meshes.remove(&mesh3d.0);
+                    mesh3d.0 = dented.original.clone();
+                    commands.entity(entity).remove::<DentedMesh>();
+                }
+                continue;
+            }
+            if dents.is_empty() && lost_wheel.is_none() {
+                continue;
+            }
+
+            // The glTF mesh is shared by every car, take a copy before bending it
+            let original = match dented {
+                Some(dented) => dented.original.clone(),
+                None => {
+                    let Some(copy) = meshes.get(&mesh3d.0).cloned() else {
+                        continue;
+                    };
+                    let original = std::mem::replace(&mut mesh3d.0, meshes.add(copy));
+                    commands.entity(entity).insert(DentedMesh { original: original.clone() });
+                    original
+                }
+            };
+
+            // Work in the car's space, where the dents are, and back
+            let car_from_mesh = car_from_world * mesh_transform.affine();
+            let mesh_from_car = car_from_mesh.inverse();
+
+            // Which vertices make up the lost wheel is read off the undented mesh, dents may have
+            // pushed some of it in past the body's sides
+            let wheel_vertices: Vec<bool> = match (lost_wheel, meshes.get(&original).and_then(|mesh| mesh.attribute(Mesh::ATTRIBUTE_POSITION))) {
+                (Some(offset), Some(VertexAttributeValues::Float32x3(positions))) => positions
+                    .iter()
+                    .map(|position| on_wheel(car_from_mesh.transform_point3(Vec3::from_array(*position)), offset))
+                    .collect(),
+                _ => Vec::new(),
+            };
+
+            let Some(mesh) = meshes.get_mut(&mesh3d.0) else {
+                continue;
+            };
+            let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION) else {
+                continue;
+            };
+
+            for (index, position) in positions.iter_mut().enumerate() {
+                let mut point = car_from_mesh.transform_point3(Vec3::from_array(*position));
+                // Squash the lost wheel down to a point so it disappears, the loose one took its place
+                if let Some(offset) = lost_wheel
+                    && wheel_vertices.get(index).copied().unwrap_or(false)
+                {
+                    point = offset;
+                }
+                for (dent, depth) in &dents {
+                    let distance = point.distance(*dent);
+                    if distance < DENT_RADIUS {
+                        let falloff = (1.0 - distance / DENT_RADIUS).powi(2);
+                        // Push towards the middle of the car
+                        point -= dent.normalize_or_zero() * depth * falloff;
+                    }
+                }
+                *position = mesh_from_car.transform_point3(point).to_array();
+            }
+        }
+    }
+}
+
+fn lose_wheels(
+    mut commands: Commands,
+    damage_assets: Res<DamageAssets>,
+    mut car_query: Query<(&mut Damage, &Transform, &Velocity)>,
+) {
+    for (mut damage, transform, velocity) in car_query.iter_mut() {
+        if damage.wheel_lost {
+            continue;
+        }
+        let Some(region) = DamageRegion::ALL
+            .into_iter()
+            .find(|region| damage.regions[*region as usize] >= WHEEL_LOSS_DAMAGE)
+        else {
+            continue;
+        };
+        // The wheel on the worst hit side flies off
+        let normal = region.normal();
+        let offset = WHEEL_OFFSETS
+            .into_iter()
+            .max_by(|a, b| a.dot(normal).total_cmp(&b.dot(normal)))
+            .unwrap_or(WHEEL_OFFSETS[0]);
+        damage.wheel_lost = true;
+        damage.pending_lost_wheel = Some(offset);
+        let position = transform.transform_point(offset);
+        let kick = transform.rotation * (normal * 6.0) + Vec3::Y * 4.0;
+
+        commands.spawn((
+            Mesh3d(damage_assets.wheel_mesh.clone()),
+            MeshMaterial3d(damage_assets.wheel_material.clone()),
+            // The cylinder stands on end, lay it on its side like the wheels on the car
+            Transform::from_translation(position).with_rotation(transform.rotation * Quat::from_rotation_x(FRAC_PI_2)),
+            RigidBody::Dynamic,
+            Collider::cylinder(0.3, 0.8),
+            Velocity {
+                linvel: velocity.linvel + kick,
+                angvel: transform.rotation * Vec3::Z * 10.0,
+            },
+            LostWheel { age: 0.0 },
+        ));
+    }
+}
+
+fn expire_lost_wheels(mut commands: Commands, time: Res<Time>, mut wheel_query: Query<(Entity, &mut LostWheel)>) {
+    for (entity, mut wheel) in wheel_query.iter_mut() {
+        wheel.age += time.delta_secs();
+        if wheel.age >= LOST_WHEEL_LIFETIME {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+fn despawn_lost_wheels(mut commands: Commands, wheel_query: Query<Entity, With<LostWheel>>) {
+    for entity in wheel_query.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+fn emit_smoke(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut damage_assets: ResMut<DamageAssets>,
+    mut car_query: Query<(&mut Damage, &Transform, &Velocity)>,
+) {
+    for (mut damage, transform, velocity) in car_query.iter_mut() {
+        let total = damage.total();
+        if total < SMOKE_DAMAGE {
+            damage.smoke_accumulator = 0.0;
+            continue;
+        }
+
+        damage.smoke_accumulator += SMOKE_RATE * (total - SMOKE_DAMAGE) / (1.0 - SMOKE_DAMAGE) * time.delta_secs();
+        while damage.smoke_accumulator >= 1.0 {
+            damage.smoke_accumulator -= 1.0;
+            let rng = &mut damage_assets.rng;
+            let jitter = Vec3::new(rng.range(-0.3, 0.3), 0.0, rng.range(-0.3, 0.3));
+            // From under the bonnet
+            let position = transform.transform_point(Vec3::new(2.2, 1.0, 0.0) + jitter);
+            let rise = Vec3::Y * rng.range(1.5, 3.0);
+            commands.spawn((
+                Mesh3d(damage_assets.smoke_mesh.clone()),
+                MeshMaterial3d(damage_assets.smoke_material.clone()),
+                Transform::from_translation(position).with_scale(Vec3::splat(0.3)),
+                NotShadowCaster,
+                Smoke {
+                    velocity: velocity.linvel * 0.3 + rise,
+                    age: 0.0,
+                },
+            ));
+        }
+    }
+}
+
+fn update_smoke(mut commands: Commands, time: Res<Time>, mut smoke_query: Query<(Entity, &mut Smoke, &mut Transform)>) {
+    let dt = time.delta_secs();
+    for (entity, mut smoke, mut transform) in smoke_query.iter_mut() {
+        smoke.age += dt;
+        if smoke.age >= SMOKE_LIFETIME {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        transform.translation += smoke.velocity * dt;
+        smoke.velocity *= (1.0 - 1.2 * dt).max(0.0);
+        // Billow out, then thin away
+        let t = smoke.age / SMOKE_LIFETIME;
+        transform.scale = Vec3::splat((0.3 + 1.5 * t.sqrt()) * (1.0 - t));
+    }
+}
+
+fn spawn_damage_huds(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    camera_query: Query<(Entity, &PlayerCamera)>,
+    hud_query: Query<(&HudLine, &ChildOf), With<DamageHudText>>,
+) {
+    let node = Node {
+        position_type: PositionType::Absolute,
+        right: Val::Px(10.),
+        bottom: Val::Px(10.),
+        ..default()
+    };
+    spawn_player_text_huds(&mut commands, &asset_server, &camera_query, &hud_query, node, Color::srgb_u8(0xAB, 0x69, 0xE7), DamageHudText);
+}
+
+fn update_damage_huds(car_query: Query<(&PlayerCar, &Damage)>, mut text_query: Query<(&mut Text, &HudLine), With<DamageHudText>>) {
+    for (mut text, hud) in text_query.iter_mut() {
+        // Nothing to show on an undamaged car
+        let label = match car_query.iter().find(|(player, _)| player.0 == hud.player) {
+            Some((_, damage)) if damage.total() > 0.0 => {
+                let regions: Vec<String> = DamageRegion::ALL
+                    .iter()
+                    .map(|region| format!("{} {:>3.0}%", region.name(), damage.regions[*region as usize] * 100.0))
+                    .collect();
+                let wheel = if damage.wheel_lost { "   wheel off" } else { "" };
+                format!("damage   {}{}", regions.join("  "), wheel)
+            }
+            _ => String::new(),
+        };
+        if text.0 != label {
+            text.0 = label;
+        }
+    }
+}
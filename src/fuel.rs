@@ -0,0 +1,252 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::Velocity;
+
+use crate::{
+    camera::PlayerCamera,
+    car::{CarOut, PlayerCar, PlayerOut},
+    input::DriveInput,
+    movement::apply_movement,
+    pickups::{PickupCollected, PickupKind},
+    run::{RunEndReason, RunStats},
+    save::SaveData,
+    settings::GameplaySettings,
+    split_screen::{spawn_player_text_huds, HudLine},
+    state::GameState,
+};
+
+/// Survival mode: every player car carries a tank that drains as it drives, fuel cans top it up,
+/// and a dry tank ends the run once the car has rolled to a stop
+pub struct FuelPlugin;
+
+impl Plugin for FuelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Running), refill_tanks)
+            .add_systems(
+                Update,
+                (
+                    escalate_drain,
+                    refuel_from_pickups,
+                    check_out_of_fuel.after(apply_movement),
+                    spawn_fuel_huds,
+                    update_fuel_huds.after(spawn_fuel_huds),
+                )
+                    .run_if(in_state(GameState::Running)),
+            );
+    }
+}
+
+const FUEL_CAPACITY: f32 = 100.0;
+// Per second: ticking over, at full throttle, and on top of that while boosting.
+// A full tank lasts a bit over a minute flat out before the drain starts climbing
+const IDLE_DRAIN: f32 = 0.2;
+const THROTTLE_DRAIN: f32 = 1.4;
+const BOOST_DRAIN: f32 = 2.0;
+const FUEL_CAN_AMOUNT: f32 = 35.0;
+// The drain climbs by the base rate again every this many meters, so runs can't go on forever
+const ESCALATION_DISTANCE: f32 = 3000.0;
+// Below this speed (m/s) a car with an empty tank has coasted to a stop
+const STOPPED_SPEED: f32 = 1.0;
+// Gauge turns purple under this fraction
+const LOW_FUEL: f32 = 0.25;
+
+#[derive(Component, Debug)]
+pub struct Fuel {
+    pub level: f32,
+    /// Multiplier on the drain, grows with distance
+    drain_scale: f32,
+}
+
+impl Default for Fuel {
+    fn default() -> Self {
+        Self {
+            level: FUEL_CAPACITY,
+            drain_scale: 1.0,
+        }
+    }
+}
+
+impl Fuel {
+    /// Burn fuel for one frame of driving, false once the tank is dry
+    pub fn burn(&mut self, drive_input: &DriveInput, dt: f32) -> bool {
+        let mut rate = IDLE_DRAIN + THROTTLE_DRAIN * drive_input.throttle.abs();
+        if drive_input.boost {
+            rate += BOOST_DRAIN;
+        }
+        self.level = (self.level - rate * self.drain_scale * dt).max(0.0);
+        self.level > 0.0
+    }
+
+    pub fn fraction(&self) -> f32 {
+        self.level / FUEL_CAPACITY
+    }
+}
+
+/// A player's fuel gauge, in their half of the screen when it's split
+#[derive(Component, Clone)]
+struct FuelHudText;
+
+fn refill_tanks(mut fuel_query: Query<&mut Fuel>) {
+    for mut fuel in fuel_query.iter_mut() {
+        *fuel = Fuel::default();
+    }
+}
+
+fn escalate_drain(mut car_query: Query<(&mut Fuel, &RunStats)>) {
+    for (mut fuel, stats) in car_query.iter_mut() {
+        fuel.drain_scale = 1.0 + stats.distance / ESCALATION_DISTANCE;
+    }
+}
+
+fn refuel_from_pickups(mut pickup_collected: MessageReader<PickupCollected>, mut fuel_query: Query<&mut Fuel>) {
+    for collected in pickup_collected.read() {
+        if collected.kind != PickupKind::FuelCan {
+            continue;
+        }
+        if let Ok(mut fuel) = fuel_query.get_mut(collected.car) {
+            fuel.level = (fuel.level + FUEL_CAN_AMOUNT).min(FUEL_CAPACITY);
+        }
+    }
+}
+
+fn check_out_of_fuel(
+    gameplay: Res<GameplaySettings>,
+    car_query: Query<(Entity, &Fuel, &Velocity), Without<PlayerOut>>,
+    mut car_out: MessageWriter<CarOut>,
+) {
+    if !gameplay.survival {
+        return;
+    }
+
+    for (car, fuel, velocity) in car_query.iter() {
+        // Rolling on an empty tank still counts, the run ends when the car stops
+        if fuel.level <= 0.0 && velocity.linvel.length() < STOPPED_SPEED {
+            car_out.write(CarOut { car, reason: RunEndReason::OutOfFuel });
+        }
+    }
+}
+
+fn spawn_fuel_huds(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    camera_query: Query<(Entity, &PlayerCamera)>,
+    hud_query: Query<(&HudLine, &ChildOf), With<FuelHudText>>,
+) {
+    // Bottom middle, clear of the now playing toast and touch controls in the corners,
+    // and just above the split screen speed readout
+    let node = Node {
+        width: Val::Percent(100.),
+        height: Val::Percent(100.),
+        position_type: PositionType::Absolute,
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::FlexEnd,
+        padding: UiRect::bottom(Val::Px(50.)),
+        ..default()
+    };
+    spawn_player_text_huds(&mut commands, &asset_server, &camera_query, &hud_query, node, Color::srgb(0.9, 0.9, 0.9), FuelHudText);
+}
+
+fn update_fuel_huds(
+    gameplay: Res<GameplaySettings>,
+    save_data: Res<SaveData>,
+    car_query: Query<(&PlayerCar, &Fuel)>,
+    mut text_query: Query<(&mut Text, &mut TextColor, &HudLine), With<FuelHudText>>,
+) {
+    for (mut text, mut text_color, hud) in text_query.iter_mut() {
+        let fuel = car_query.iter().find(|(player, _)| player.0 == hud.player).map(|(_, fuel)| fuel);
+        let label = match fuel {
+            Some(fuel) if gameplay.survival => {
+                let steps = ((fuel.fraction() * 10.0).ceil() as usize).min(10);
+                *text_color = if fuel.fraction() < LOW_FUEL {
+                    TextColor(Color::srgb_u8(0xAB, 0x69, 0xE7))
+                } else {
+                    TextColor(Color::srgb(0.9, 0.9, 0.9))
+                };
+                format!(
+                    "fuel [{}{}]   best {:.0} m",
+                    "#".repeat(steps),
+                    "-".repeat(10 - steps),
+                    save_data.high_scores.survival_distance
+                )
+            }
+            _ => String::new(),
+        };
+        if text.0 != label {
+            text.0 = label;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn idle_and_throttle_drain() {
+        let mut fuel = Fuel::default();
+        assert!(fuel.burn(&DriveInput::default(), 1.0));
+        assert!(close(fuel.level, FUEL_CAPACITY - IDLE_DRAIN));
+
+        let mut fuel = Fuel::default();
+        let full_throttle = DriveInput {
+            throttle: 1.0,
+            ..default()
+        };
+        fuel.burn(&full_throttle, 1.0);
+        assert!(close(fuel.level, FUEL_CAPACITY - IDLE_DRAIN - THROTTLE_DRAIN));
+
+        // Reversing burns the same as going forward
+        let mut fuel = Fuel::default();
+        let reverse = DriveInput {
+            throttle: -1.0,
+            ..default()
+        };
+        fuel.burn(&reverse, 1.0);
+        assert!(close(fuel.level, FUEL_CAPACITY - IDLE_DRAIN - THROTTLE_DRAIN));
+    }
+
+    #[test]
+    fn boost_burns_extra() {
+        let mut fuel = Fuel::default();
+        let boosting = DriveInput {
+            throttle: 1.0,
+            boost: true,
+            ..default()
+        };
+        fuel.burn(&boosting, 1.0);
+        assert!(close(fuel.level, FUEL_CAPACITY - IDLE_DRAIN - THROTTLE_DRAIN - BOOST_DRAIN));
+    }
+
+    #[test]
+    fn drain_scale_multiplies_the_rate() {
+        let mut fuel = Fuel {
+            drain_scale: 2.0,
+            ..default()
+        };
+        let full_throttle = DriveInput {
+            throttle: 1.0,
+            ..default()
+        };
+        fuel.burn(&full_throttle, 0.5);
+        assert!(close(fuel.level, FUEL_CAPACITY - (IDLE_DRAIN + THROTTLE_DRAIN)));
+    }
+
+    #[test]
+    fn tank_runs_dry_and_stays_at_zero() {
+        let mut fuel = Fuel {
+            level: 0.1,
+            ..default()
+        };
+        let boosting = DriveInput {
+            throttle: 1.0,
+            boost: true,
+            ..default()
+        };
+        assert!(!fuel.burn(&boosting, 1.0));
+        assert_eq!(fuel.level, 0.0);
+        assert_eq!(fuel.fraction(), 0.0);
+    }
+}
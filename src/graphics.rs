@@ -0,0 +1,177 @@
+use bevy::{
+    light::{DirectionalLightShadowMap, PointLightShadowMap},
+    prelude::*,
+    window::{PresentMode, PrimaryWindow},
+};
+use serde::{Deserialize, Serialize};
+
+pub struct GraphicsPlugin;
+
+impl Plugin for GraphicsPlugin {
+    fn build(&self, app: &mut App) {
+        // Wireframes need polygon line mode, which WebGL2 doesn't have
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_plugins(bevy::pbr::wireframe::WireframePlugin::default());
+
+        app.add_systems(Update, (apply_graphics_settings, apply_camera_settings));
+    }
+}
+
+pub const MIN_FOV: f32 = 35.0;
+pub const MAX_FOV: f32 = 90.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GraphicsPreset {
+    Low,
+    Medium,
+    High,
+    /// One of the preset values was changed by hand
+    Custom,
+}
+
+impl GraphicsPreset {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GraphicsPreset::Low => "low",
+            GraphicsPreset::Medium => "medium",
+            GraphicsPreset::High => "high",
+            GraphicsPreset::Custom => "custom",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            GraphicsPreset::Low => GraphicsPreset::Medium,
+            GraphicsPreset::Medium => GraphicsPreset::High,
+            GraphicsPreset::High | GraphicsPreset::Custom => GraphicsPreset::Low,
+        }
+    }
+}
+
+/// Render quality preferences, applied live and kept in the save data
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphicsSettings {
+    pub preset: GraphicsPreset,
+    pub shadows: bool,
+    pub shadow_map_size: usize,
+    /// MSAA sample count, 1 for off. WebGL2 only supports 1 or 4
+    pub msaa_samples: u32,
+    /// Fraction of the canvas resolution to render at, only takes effect in the browser
+    pub render_scale: f32,
+    pub wireframe: bool,
+    pub vsync: bool,
+    /// Vertical field of view in degrees
+    pub fov: f32,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            preset: GraphicsPreset::High,
+            shadows: true,
+            shadow_map_size: 2048,
+            msaa_samples: 4,
+            render_scale: 1.0,
+            wireframe: false,
+            vsync: true,
+            // Bevy's default perspective
+            fov: 45.0,
+        }
+    }
+}
+
+impl GraphicsSettings {
+    /// Set the quality values for a preset, leaving personal preferences like FOV alone
+    pub fn apply_preset(&mut self, preset: GraphicsPreset) {
+        let (shadows, shadow_map_size, msaa_samples, render_scale) = match preset {
+            GraphicsPreset::Low => (false, 512, 1, 0.5),
+            GraphicsPreset::Medium => (true, 1024, 1, 0.75),
+            GraphicsPreset::High => (true, 2048, 4, 1.0),
+            GraphicsPreset::Custom => return,
+        };
+        self.preset = preset;
+        self.shadows = shadows;
+        self.shadow_map_size = shadow_map_size;
+        self.msaa_samples = msaa_samples;
+        self.render_scale = render_scale;
+    }
+
+    /// Keep loaded values within what the settings menu and presets can reach
+    pub fn clamp(&mut self) {
+        self.shadow_map_size = self.shadow_map_size.clamp(512, 4096).next_power_of_two();
+        self.render_scale = self.render_scale.clamp(0.25, 1.0);
+        self.fov = self.fov.clamp(MIN_FOV, MAX_FOV);
+    }
+
+    pub fn msaa(&self) -> Msaa {
+        match self.msaa_samples {
+            0 | 1 => Msaa::Off,
+            2 if !cfg!(target_arch = "wasm32") => Msaa::Sample2,
+            8 if !cfg!(target_arch = "wasm32") => Msaa::Sample8,
+            _ => Msaa::Sample4,
+        }
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        match (self.vsync, cfg!(target_arch = "wasm32")) {
+            // Fifo doesn't work on WASM, use AutoVsync instead
+            (true, true) => PresentMode::AutoVsync,
+            (true, false) => PresentMode::Fifo,
+            (false, _) => PresentMode::AutoNoVsync,
+        }
+    }
+}
+
+fn apply_graphics_settings(
+    settings: Res<GraphicsSettings>,
+    mut directional_shadow_map: ResMut<DirectionalLightShadowMap>,
+    mut point_shadow_map: ResMut<PointLightShadowMap>,
+    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
+    #[cfg(not(target_arch = "wasm32"))] mut wireframe_config: ResMut<bevy::pbr::wireframe::WireframeConfig>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    // Whether lights cast at all is up to lighting, which checks settings.shadows
+    directional_shadow_map.size = settings.shadow_map_size;
+    point_shadow_map.size = settings.shadow_map_size;
+
+    if let Ok(mut window) = window_query.single_mut() {
+        window.present_mode = settings.present_mode();
+
+        // The canvas is stretched to fit the page, so a lower scale factor renders
+        // fewer pixels and lets the browser upscale them
+        if cfg!(target_arch = "wasm32") {
+            let base_scale_factor = window.resolution.base_scale_factor();
+            window
+                .resolution
+                .set_scale_factor_override(Some(base_scale_factor * settings.render_scale));
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        wireframe_config.global = settings.wireframe;
+    }
+}
+
+fn apply_camera_settings(
+    mut commands: Commands,
+    settings: Res<GraphicsSettings>,
+    added_camera_query: Query<(), Added<Camera3d>>,
+    mut camera_query: Query<(Entity, &mut Projection), With<Camera3d>>,
+) {
+    // Cameras get swapped between states, new ones need the settings too
+    if !settings.is_changed() && added_camera_query.is_empty() {
+        return;
+    }
+
+    for (entity, mut projection) in camera_query.iter_mut() {
+        if let Projection::Perspective(perspective) = projection.as_mut() {
+            perspective.fov = settings.fov.to_radians();
+        }
+        commands.entity(entity).insert(settings.msaa());
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Write},
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+use bevy::{prelude::*, time::TimeUpdateStrategy};
+use bevy_rapier3d::prelude::{CollidingEntities, PhysicsSet, QueryFilter, ReadRapierContext, Sensor, TimestepMode, Velocity};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    car::{PlayerCar, PlayerOut},
+    impacts::on_ground,
+    input::{get_car_movement, DriveInput},
+    movement::{apply_movement, CarMovements},
+    net::start_when_loaded,
+    recover::RecoveryAllowed,
+    run::LastRun,
+    state::GameState,
+};
+
+/// Lets an external agent drive the first player's car over a local TCP socket, one JSON line
+/// per command and one JSON observation line back. Started with `--gym [port]`
+pub struct GymPlugin;
+
+impl Plugin for GymPlugin {
+    fn build(&self, app: &mut App) {
+        let Some(port) = gym_port() else {
+            return;
+        };
+        let listener = match TcpListener::bind(("127.0.0.1", port)).and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            Ok(listener)
+        }) {
+            Ok(listener) => listener,
+            Err(error) => {
+                bevy::log::error!("Failed to open gym socket on port {}: {}", port, error);
+                return;
+            }
+        };
+        bevy::log::info!("Gym listening on 127.0.0.1:{}", port);
+
+        app.insert_resource(Gym {
+            listener,
+            connection: None,
+            pending: None,
+        })
+        // Every frame is the same length and every frame is one physics step, so the same
+        // actions from the same start always give the same run
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(STEP_SECONDS)))
+        .insert_resource(TimestepMode::Fixed {
+            dt: STEP_SECONDS,
+            substeps: 1,
+        })
+        // An episode ends when the car goes out, rather than carrying on from a breadcrumb
+        .insert_resource(RecoveryAllowed(false))
+        .add_systems(Update, start_when_loaded.run_if(in_state(GameState::Setup)))
+        .add_systems(
+            Update,
+            (
+                accept_agent,
+                receive_command.after(accept_agent),
+                drive_gym_car
+                    .after(receive_command)
+                    .after(get_car_movement)
+                    .before(apply_movement)
+                    .run_if(in_state(GameState::Running)),
+            )
+                .run_if(in_state(GameState::Running).or(in_state(GameState::GameOver))),
+        )
+        .add_systems(
+            PostUpdate,
+            send_observation
+                .after(PhysicsSet::Writeback)
+                .run_if(in_state(GameState::Running).or(in_state(GameState::GameOver))),
+        );
+    }
+}
+
+pub const DEFAULT_GYM_PORT: u16 = 7979;
+const STEP_SECONDS: f32 = 1.0 / 60.0;
+// Terrain heights are sampled on a square grid around the car, turned with it
+const HEIGHT_SAMPLES: i32 = 5; // per side, odd so the car sits on the middle sample
+const HEIGHT_SPACING: f32 = 4.0;
+
+fn gym_port() -> Option<u16> {
+    if cfg!(target_arch = "wasm32") {
+        return None;
+    }
+    let args: Vec<String> = std::env::args().collect();
+    let index = args.iter().position(|arg| arg == "--gym")?;
+    Some(args.get(index + 1).and_then(|port| port.parse().ok()).unwrap_or(DEFAULT_GYM_PORT))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum GymCommand {
+    /// Drive one physics step with this action
+    Step { action: DriveInput },
+    /// Put every car back at its start, through the same path as playing again after game over
+    Reset,
+}
+
+#[derive(Debug, Serialize)]
+struct Observation {
+    position: [f32; 3],
+    rotation: [f32; 4],
+    linvel: [f32; 3],
+    angvel: [f32; 3],
+    /// Touching anything, the same test the stuck check uses
+    grounded: bool,
+    /// Ground height relative to the car, row by row from behind-left to ahead-right.
+    /// Null where there's no ground, off the edge of the map
+    heights: Vec<Option<f32>>,
+    done: bool,
+    reason: Option<String>,
+}
+
+#[derive(Resource)]
+struct Gym {
+    listener: TcpListener,
+    connection: Option<(BufReader<TcpStream>, TcpStream)>,
+    pending: Option<Pending>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pending {
+    /// This frame steps the car, answer once physics has run
+    Step(DriveInput),
+    /// Waiting for the reset to get through game over and back to running
+    Reset { through_game_over: bool },
+}
+
+fn accept_agent(mut gym: ResMut<Gym>) {
+    if gym.connection.is_some() {
+        return;
+    }
+    match gym.listener.accept() {
+        Ok((stream, address)) => {
+            // The agent sets the pace from here on, wait for each of its commands
+            let stream = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_nodelay(true))
+                .and_then(|_| stream.try_clone().map(|writer| (BufReader::new(stream), writer)));
+            match stream {
+                Ok(connection) => {
+                    bevy::log::info!("Gym agent connected from {}", address);
+                    gym.connection = Some(connection);
+                    gym.pending = None;
+                }
+                Err(error) => bevy::log::warn!("Gym agent connection failed: {}", error),
+            }
+        }
+        Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+        Err(error) => bevy::log::warn!("Gym accept failed: {}", error),
+    }
+}
+
+fn receive_command(
+    mut gym: ResMut<Gym>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    // A reset takes a couple of frames of state changes, keep it moving before reading anything new
+    match (gym.pending, state.get()) {
+        (Some(Pending::Reset { through_game_over: false }), GameState::GameOver) => {
+            next_state.set(GameState::Running);
+            gym.pending = Some(Pending::Reset { through_game_over: true });
+            return;
+        }
+        (Some(Pending::Reset { .. }), _) => return,
+        _ => {}
+    }
+
+    let Some((reader, _)) = gym.connection.as_mut() else {
+        return;
+    };
+    // Blocks the whole game until the agent decides, that's what keeps it in lock-step
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) | Err(_) => {
+            bevy::log::info!("Gym agent disconnected");
+            gym.connection = None;
+            gym.pending = None;
+            return;
+        }
+        Ok(_) => {}
+    }
+
+    gym.pending = match serde_json::from_str::<GymCommand>(&line) {
+        // After game over the car stays put until a reset, the agent just sees done again
+        Ok(GymCommand::Step { action }) => Some(Pending::Step(action)),
+        Ok(GymCommand::Reset) => {
+            let through_game_over = *state.get() == GameState::GameOver;
+            next_state.set(if through_game_over { GameState::Running } else { GameState::GameOver });
+            Some(Pending::Reset { through_game_over })
+        }
+        Err(error) => {
+            bevy::log::warn!("Bad gym command {:?}: {}", line.trim(), error);
+            // Answer anyway so the agent isn't left waiting
+            Some(Pending::Step(DriveInput::default()))
+        }
+    };
+}
+
+/// The agent's action replaces whatever the keyboard or gamepad asked for
+fn drive_gym_car(
+    gym: Res<Gym>,
+    mut car_query: Query<(&PlayerCar, &mut CarMovements, &mut DriveInput), Without<PlayerOut>>,
+) {
+    if gym.connection.is_none() {
+        return;
+    }
+    let Some((_, mut movements, mut drive_input)) =
+        car_query.iter_mut().find(|(player, ..)| player.0 == 0)
+    else {
+        return;
+    };
+
+    movements.0.clear();
+    *drive_input = match gym.pending {
+        Some(Pending::Step(action)) => action,
+        _ => DriveInput::default(),
+    };
+    drive_input.apply(&mut movements);
+}
+
+fn send_observation(
+    mut gym: ResMut<Gym>,
+    state: Res<State<GameState>>,
+    last_run: Res<LastRun>,
+    read_rapier_context: ReadRapierContext,
+    car_query: Query<(Entity, &PlayerCar, &Transform, &Velocity, &CollidingEntities, Has<PlayerOut>)>,
+    sensor_query: Query<(), With<Sensor>>,
+) {
+    let ready = match gym.pending {
+        Some(Pending::Step(_)) => true,
+        // The cars were put back at their starts as we left game over
+        Some(Pending::Reset { through_game_over: true }) => *state.get() == GameState::Running,
+        _ => false,
+    };
+    if !ready {
+        return;
+    }
+    let Some((car, _, transform, velocity, colliding_entities, out)) =
+        car_query.iter().find(|(_, player, ..)| player.0 == 0)
+    else {
+        return;
+    };
+    let Ok(rapier_context) = read_rapier_context.single() else {
+        return;
+    };
+
+    let filter = QueryFilter::default().exclude_rigid_body(car).exclude_sensors();
+    // Forward is the car's x axis, the grid only turns around y so pitching doesn't tilt it
+    let (yaw, _, _) = transform.rotation.to_euler(EulerRot::YXZ);
+    let half = HEIGHT_SAMPLES / 2;
+    let mut heights = Vec::new();
+    for row in -half..=half {
+        for column in -half..=half {
+            let offset = Quat::from_rotation_y(yaw) * Vec3::new(row as f32, 0.0, column as f32) * HEIGHT_SPACING;
+            let origin = transform.translation + offset + Vec3::Y * 100.0;
+            let height = rapier_context
+                .cast_ray(origin, Vec3::NEG_Y, 1000.0, true, filter)
+                .map(|(_entity, distance)| origin.y - distance - transform.translation.y);
+            heights.push(height);
+        }
+    }
+
+    let done = out || *state.get() == GameState::GameOver;
+    let observation = Observation {
+        position: transform.translation.to_array(),
+        rotation: transform.rotation.to_array(),
+        linvel: velocity.linvel.to_array(),
+        angvel: velocity.angvel.to_array(),
+        grounded: on_ground(colliding_entities, &sensor_query),
+        heights,
+        done,
+        reason: done
+            .then(|| last_run.0.iter().find(|run| run.player == 0))
+            .flatten()
+            .map(|run| run.reason.title().to_lowercase()),
+    };
+    gym.pending = None;
+
+    let Some((_, writer)) = gym.connection.as_mut() else {
+        return;
+    };
+    let sent = serde_json::to_string(&observation)
+        .map_err(std::io::Error::other)
+        .and_then(|line| writeln!(writer, "{}", line));
+    if let Err(error) = sent {
+        bevy::log::info!("Gym agent disconnected: {}", error);
+        gym.connection = None;
+    }
+}
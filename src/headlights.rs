@@ -0,0 +1,198 @@
+use bevy::{gltf::Gltf, prelude::*};
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::{assets::SceneResource, input::CarAction, lighting::TimeOfDay, state::GameState};
+
+pub struct HeadlightsPlugin;
+
+impl Plugin for HeadlightsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                capture_headlight_material,
+                attach_headlight_lamps,
+                give_cars_own_headlight_material,
+                toggle_headlights,
+                auto_headlights,
+                sync_headlights,
+            )
+                .chain()
+                .run_if(in_state(GameState::Running)),
+        );
+    }
+}
+
+// Lamp positions on the front of the CAR mesh, in the car's local space
+const LAMP_OFFSETS: [Vec3; 2] = [Vec3::new(3.9, -0.17, 0.9), Vec3::new(3.9, -0.17, -0.9)];
+const LAMP_INTENSITY: f32 = 2_000_000.0;
+
+#[derive(Component, Debug)]
+pub struct Headlights {
+    pub on: bool,
+    /// Follow the time of day until the driver flips the switch themselves
+    pub auto: bool,
+}
+
+impl Default for Headlights {
+    fn default() -> Self {
+        Self { on: false, auto: true }
+    }
+}
+
+#[derive(Component)]
+struct HeadlightLamp;
+
+/// The HEADLIGHT material as loaded, and its emissive color for lights on. Never written to,
+/// each car glows through its own copy
+#[derive(Resource)]
+struct HeadlightMaterial {
+    handle: Handle<StandardMaterial>,
+    emissive_on: LinearRgba,
+}
+
+/// A car's own copy of the HEADLIGHT material, so its glow follows only its own lights
+#[derive(Component)]
+struct HeadlightGlow(Handle<StandardMaterial>);
+
+fn capture_headlight_material(
+    mut commands: Commands,
+    headlight_material: Option<Res<HeadlightMaterial>>,
+    scene_assets: Res<SceneResource>,
+    assets_gltf: Res<Assets<Gltf>>,
+    materials: Res<Assets<StandardMaterial>>,
+) {
+    if headlight_material.is_some() {
+        return;
+    }
+    let Some(handle) = assets_gltf
+        .get(&scene_assets.handle)
+        .and_then(|gltf| gltf.named_materials.get("HEADLIGHT"))
+    else {
+        return;
+    };
+
+    if let Some(material) = materials.get(handle) {
+        commands.insert_resource(HeadlightMaterial {
+            handle: handle.clone(),
+            emissive_on: material.emissive,
+        });
+    }
+}
+
+fn attach_headlight_lamps(mut commands: Commands, car_query: Query<Entity, Added<Headlights>>) {
+    for car_entity in car_query.iter() {
+        commands.entity(car_entity).with_children(|car| {
+            for offset in LAMP_OFFSETS {
+                car.spawn((
+                    SpotLight {
+                        color: Color::srgb(1.0, 0.95, 0.8),
+                        intensity: 0.0,
+                        range: 80.0,
+                        inner_angle: 0.25,
+                        outer_angle: 0.5,
+                        // Shadows from two more lights would be too much for WebGL2
+                        shadows_enabled: false,
+                        ..default()
+                    },
+                    // Spot lights shine down their local -Z, aim it forward and a little down
+                    Transform::from_translation(offset).looking_to(Vec3::new(1.0, -0.12, 0.0), Vec3::Y),
+                    HeadlightLamp,
+                ));
+            }
+        });
+    }
+}
+
+fn give_cars_own_headlight_material(
+    mut commands: Commands,
+    headlight_material: Option<Res<HeadlightMaterial>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    car_query: Query<(Entity, &Headlights), Without<HeadlightGlow>>,
+    children_query: Query<&Children>,
+    mut material_query: Query<&mut MeshMaterial3d<StandardMaterial>>,
+) {
+    let Some(headlight_material) = headlight_material else {
+        return;
+    };
+
+    for (car, headlights) in car_query.iter() {
+        // The car's scene spawns a few frames after the car, keep looking until its lamps show up
+        let lamps: Vec<Entity> = children_query
+            .iter_descendants(car)
+            .filter(|entity| {
+                material_query
+                    .get(*entity)
+                    .is_ok_and(|material| material.0 == headlight_material.handle)
+            })
+            .collect();
+        if lamps.is_empty() {
+            continue;
+        }
+        let Some(mut copy) = materials.get(&headlight_material.handle).cloned() else {
+            continue;
+        };
+
+        copy.emissive = if headlights.on {
+            headlight_material.emissive_on
+        } else {
+            LinearRgba::BLACK
+        };
+        let handle = materials.add(copy);
+        for lamp in lamps {
+            if let Ok(mut material) = material_query.get_mut(lamp) {
+                material.0 = handle.clone();
+            }
+        }
+        commands.entity(car).insert(HeadlightGlow(handle));
+    }
+}
+
+fn toggle_headlights(mut car_query: Query<(&mut Headlights, &ActionState<CarAction>)>) {
+    for (mut headlights, action_state) in car_query.iter_mut() {
+        if action_state.just_pressed(&CarAction::Headlights) {
+            headlights.on = !headlights.on;
+            headlights.auto = false;
+        }
+    }
+}
+
+fn auto_headlights(time_of_day: Option<Res<TimeOfDay>>, mut car_query: Query<&mut Headlights>) {
+    let Some(time_of_day) = time_of_day else {
+        return;
+    };
+
+    // Come on a little before the sun is fully down
+    let dark = time_of_day.sun_elevation() < 0.05;
+    for mut headlights in car_query.iter_mut() {
+        if headlights.auto && headlights.on != dark {
+            headlights.on = dark;
+        }
+    }
+}
+
+fn sync_headlights(
+    headlight_material: Option<Res<HeadlightMaterial>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    car_query: Query<(&Headlights, &Children, Option<&HeadlightGlow>), Changed<Headlights>>,
+    mut lamp_query: Query<&mut SpotLight, With<HeadlightLamp>>,
+) {
+    for (headlights, children, glow) in car_query.iter() {
+        for child in children.iter() {
+            if let Ok(mut lamp) = lamp_query.get_mut(child) {
+                lamp.intensity = if headlights.on { LAMP_INTENSITY } else { 0.0 };
+            }
+        }
+
+        // Keep the car's own copy of the HEADLIGHT material glowing in step with its lamps
+        if let (Some(headlight_material), Some(glow)) = (&headlight_material, glow)
+            && let Some(material) = materials.get_mut(&glow.0)
+        {
+            material.emissive = if headlights.on {
+                headlight_material.emissive_on
+            } else {
+                LinearRgba::BLACK
+            };
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::{CollidingEntities, CollisionEvent, ContactForceEvent, Sensor, Velocity};
+
+use crate::{car::Car, state::GameState};
+
+pub struct ImpactsPlugin;
+
+impl Plugin for ImpactsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<CarLanded>()
+            .add_message::<CarImpact>()
+            .add_systems(
+                Update,
+                (
+                    // Landings compare against last frame's air time, so read them before it resets
+                    detect_landings.before(track_airborne),
+                    track_airborne,
+                    detect_impacts,
+                )
+                    .run_if(in_state(GameState::Running)),
+            );
+    }
+}
+
+// Shorter hops than this are just bumps in the dunes
+const MIN_LANDING_AIR_TIME: f32 = 0.25;
+
+/// A car touched down after being in the air
+#[derive(Message, Debug, Clone, Copy)]
+pub struct CarLanded {
+    pub car: Entity,
+    /// Downward speed just before touching down
+    pub impact_speed: f32,
+    pub air_time: f32,
+}
+
+/// A car hit something hard enough for rapier to report the contact force
+#[derive(Message, Debug, Clone, Copy)]
+pub struct CarImpact {
+    pub car: Entity,
+    pub force: f32,
+    /// World-space direction of the strongest contact force, as felt by this car
+    pub direction: Vec3,
+}
+
+#[derive(Component, Debug, Default)]
+pub struct Airborne {
+    pub air_time: f32,
+    fall_speed: f32,
+}
+
+/// Touching something solid. Pickup sensors show up in CollidingEntities too, but don't hold a car up
+pub fn on_ground(colliding_entities: &CollidingEntities, sensor_query: &Query<(), With<Sensor>>) -> bool {
+    colliding_entities.iter().any(|entity| !sensor_query.contains(entity))
+}
+
+fn track_airborne(
+    time: Res<Time>,
+    mut car_query: Query<(&mut Airborne, &Velocity, &CollidingEntities), With<Car>>,
+    sensor_query: Query<(), With<Sensor>>,
+) {
+    for (mut airborne, velocity, colliding_entities) in car_query.iter_mut() {
+        if !on_ground(colliding_entities, &sensor_query) {
+            airborne.air_time += time.delta_secs();
+            airborne.fall_speed = (-velocity.linvel.y).max(0.0);
+        } else {
+            airborne.air_time = 0.0;
+            airborne.fall_speed = 0.0;
+        }
+    }
+}
+
+fn detect_landings(
+    mut collision_events: MessageReader<CollisionEvent>,
+    car_query: Query<&Airborne, With<Car>>,
+    sensor_query: Query<(), With<Sensor>>,
+    mut car_landed: MessageWriter<CarLanded>,
+) {
+    for event in collision_events.read() {
+        let CollisionEvent::Started(collider1, collider2, _) = *event else {
+            continue;
+        };
+        // Flying through a pickup isn't touching down
+        if sensor_query.contains(collider1) || sensor_query.contains(collider2) {
+            continue;
+        }
+
+        for car in [collider1, collider2] {
+            if let Ok(airborne) = car_query.get(car)
+                && airborne.air_time >= MIN_LANDING_AIR_TIME
+            {
+                car_landed.write(CarLanded {
+                    car,
+                    impact_speed: airborne.fall_speed,
+                    air_time: airborne.air_time,
+                });
+            }
+        }
+    }
+}
+
+fn detect_impacts(
+    mut contact_force_events: MessageReader<ContactForceEvent>,
+    car_query: Query<(), With<Car>>,
+    mut car_impact: MessageWriter<CarImpact>,
+) {
+    for event in contact_force_events.read() {
+        for car in [event.collider1, event.collider2] {
+            if car_query.contains(car) {
+                // Rapier reports the force on the pair's first collider, the second feels it the other way
+                let direction = if car == event.collider1 {
+                    event.max_force_direction
+                } else {
+                    -event.max_force_direction
+                };
+                car_impact.write(CarImpact {
+                    car,
+                    force: event.total_force_magnitude,
+                    direction,
+                });
+            }
+        }
+    }
+}
@@ -1,14 +1,19 @@
 use bevy::prelude::*;
 use leafwing_input_manager::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::movement::{CarMovement, CarMovements};
+use crate::{
+    car::PlayerOut,
+    fuel::Fuel,
+    movement::{CarMovement, CarMovements},
+    pickups::OrbBoost,
+    settings::{ControlSettings, GameplaySettings},
+};
 
-#[derive(Actionlike, PartialEq, Eq, Clone, Copy, Hash, Debug, Reflect)]
+#[derive(Actionlike, PartialEq, Eq, Clone, Copy, Hash, Debug, Reflect, Serialize, Deserialize)]
 pub enum CarAction {
-    #[actionlike(Button)]
-    PushForward,
-    #[actionlike(Button)]
-    PushBackward,
+    #[actionlike(Axis)]
+    Throttle, // Forward is positive, brake / reverse negative
     #[actionlike(Button)]
     TurnLeft,
     #[actionlike(Button)]
@@ -18,80 +23,205 @@ pub enum CarAction {
     #[actionlike(Button)]
     Boost,
     #[actionlike(Button)]
+    Handbrake,
+    #[actionlike(Button)]
+    Headlights,
+    #[actionlike(Button)]
     Reset,
+    #[actionlike(Button)]
+    Settings,
     #[actionlike(DualAxis)]
     CameraOrbit, // Left stick for camera control
     #[actionlike(Button)]
+    CycleCamera, // Follow the next car
+    #[actionlike(Button)]
     PlayAgain, // For game over screen
+    #[actionlike(Button)]
+    LockTimeOfDay, // Stop and start the day cycle
+    #[actionlike(Button)]
+    EarlierTimeOfDay,
+    #[actionlike(Button)]
+    LaterTimeOfDay,
 }
 
-pub fn get_car_movement(
-    mut query: Query<(&mut CarMovements, &mut Transform, &ActionState<CarAction>)>,
-) {
-    let turn_factor = 23.0; // turn factor to compensate for high gravity
-    let push_factor = 13.0;
-    let boost_factor = 6.0;
+// Shared with the AI drivers so they push the car exactly as hard as a player can
+pub const TURN_FACTOR: f32 = 23.0; // turn factor to compensate for high gravity
+pub const PUSH_FACTOR: f32 = 13.0;
+pub const BOOST_FACTOR: f32 = 6.0;
 
-    if let Ok((mut movements, mut transform, action_state)) = query.single_mut() {
-        // Both keyboard and gamepad work simultaneously
-        let current_push_factor = if action_state.pressed(&CarAction::Boost) {
-            push_factor + boost_factor
+/// Throttle after the deadzone and keyboard ramp, what actually drives the car
+#[derive(Component, Debug, Default)]
+pub struct Throttle {
+    pub value: f32,
+    last_raw: f32,
+    ramping: bool,
+}
+
+/// Drop trigger values inside the deadzone and rescale the rest back to the full range
+fn apply_deadzone(value: f32, deadzone: f32) -> f32 {
+    if value.abs() <= deadzone {
+        0.0
+    } else {
+        value.signum() * (value.abs() - deadzone) / (1.0 - deadzone)
+    }
+}
+
+/// One frame of driving after deadzones and ramps, what a car's controls boil down to.
+/// Also what a network client sends the server for its car, and a gym agent sends as its action
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DriveInput {
+    /// Forward is positive, brake / reverse negative
+    pub throttle: f32,
+    /// Right is positive, keyboard and stick add up
+    pub turn: f32,
+    pub boost: bool,
+    pub handbrake: bool,
+    /// Put the car back on its wheels this frame, handled by the recovery pose search in recover
+    pub reset: bool,
+}
+
+impl DriveInput {
+    pub fn apply(&self, movements: &mut CarMovements) {
+        let current_push_factor = if self.boost {
+            PUSH_FACTOR + BOOST_FACTOR
         } else {
-            push_factor
+            PUSH_FACTOR
         };
 
-        // Forward/backward movement
-        if action_state.pressed(&CarAction::PushForward) {
-            movements.0.push(CarMovement::PushForward(current_push_factor))
+        // Forward/backward movement, proportional to how far the trigger is pressed
+        if self.throttle > 0.0 {
+            movements.0.push(CarMovement::PushForward(current_push_factor * self.throttle))
+        }
+        if self.throttle < 0.0 {
+            movements.0.push(CarMovement::PushBackward(current_push_factor * -self.throttle))
+        }
+        if self.handbrake {
+            movements.0.push(CarMovement::Handbrake)
+        }
+
+        if self.turn < 0.0 {
+            movements.0.push(CarMovement::TurnLeft(TURN_FACTOR * -self.turn))
+        }
+        if self.turn > 0.0 {
+            movements.0.push(CarMovement::TurnRight(TURN_FACTOR * self.turn))
         }
-        if action_state.pressed(&CarAction::PushBackward) {
-            movements.0.push(CarMovement::PushBackward(current_push_factor))
+    }
+}
+
+pub fn get_car_movement(
+    time: Res<Time>,
+    controls: Res<ControlSettings>,
+    gameplay: Res<GameplaySettings>,
+    mut query: Query<(&mut CarMovements, &mut Throttle, &mut DriveInput, Option<&mut Fuel>, Has<OrbBoost>, &ActionState<CarAction>), Without<PlayerOut>>,
+) {
+    // Every car with controls, AI cars fill in their movements themselves
+    for (mut movements, mut throttle, mut drive_input, fuel, orb_boost, action_state) in query.iter_mut() {
+        // Keys and touch buttons jump straight from rest to full, triggers pass through the
+        // values in between. Only the digital jumps get ramped, triggers stay proportional
+        let raw = apply_deadzone(action_state.value(&CarAction::Throttle), controls.trigger_deadzone);
+        if raw.abs() >= 1.0 && throttle.last_raw == 0.0 {
+            throttle.ramping = true;
+        } else if raw.abs() < 1.0 {
+            throttle.ramping = false;
         }
-        
-        // Keyboard turning (button presses) - always works, even if gamepad is also being used
+        throttle.last_raw = raw;
+
+        throttle.value = if throttle.ramping && controls.keyboard_ramp > 0.0 {
+            // Switching between forward and reverse starts the ramp over
+            let current = if throttle.value.signum() == raw.signum() { throttle.value } else { 0.0 };
+            let step = time.delta_secs() / controls.keyboard_ramp;
+            current + (raw - current).clamp(-step, step)
+        } else {
+            raw
+        };
+
+        // Keyboard turning (button presses) and gamepad turning (right stick X) work simultaneously
+        let mut turn = 0.0;
         if action_state.pressed(&CarAction::TurnLeft) {
-            movements.0.push(CarMovement::TurnLeft(turn_factor))
+            turn -= 1.0;
         }
         if action_state.pressed(&CarAction::TurnRight) {
-            movements.0.push(CarMovement::TurnRight(turn_factor))
+            turn += 1.0;
         }
-        
-        // Gamepad turning (dual axis - right stick X) - works simultaneously with keyboard
-        let turn_axis = action_state.axis_pair(&CarAction::TurnAxis);
-        let turn_x = turn_axis.x;
+        let turn_x = action_state.axis_pair(&CarAction::TurnAxis).x;
         if turn_x.abs() > 0.01 {
-            let turn_force = turn_factor * turn_x.abs(); // Use same turn factor for gamepad
-            if turn_x < 0.0 {
-                movements.0.push(CarMovement::TurnLeft(turn_force))
-            } else {
-                movements.0.push(CarMovement::TurnRight(turn_force))
-            }
+            turn += turn_x;
         }
 
-        if action_state.just_pressed(&CarAction::Reset) {
-            let y = transform.rotation.y;
-            let w = transform.rotation.w;
-            transform.rotation = Quat::from_vec4(Vec4::new(0.0, y, 0.0, w));
+        // Both keyboard and gamepad work simultaneously
+        *drive_input = DriveInput {
+            throttle: throttle.value,
+            turn,
+            boost: action_state.pressed(&CarAction::Boost),
+            handbrake: action_state.pressed(&CarAction::Handbrake),
+            reset: action_state.just_pressed(&CarAction::Reset),
+        };
+
+        // In survival mode a dry tank leaves the car coasting, it can still steer and handbrake
+        if gameplay.survival
+            && let Some(mut fuel) = fuel
+            && !fuel.burn(&drive_input, time.delta_secs())
+        {
+            drive_input.throttle = 0.0;
+            drive_input.boost = false;
         }
+        // A boost orb's boost comes free, it doesn't burn any fuel. Still nothing without gas
+        if orb_boost {
+            drive_input.boost = true;
+        }
+        drive_input.apply(&mut movements);
     }
 }
 
+/// The first player's controls, their custom bindings from the save or the defaults
+pub fn player_input_map(key_bindings: Option<&InputMap<CarAction>>) -> InputMap<CarAction> {
+    key_bindings.cloned().unwrap_or_else(default_input_map)
+}
+
 pub fn default_input_map() -> InputMap<CarAction> {
+    let mut input_map = keyboard_input_map();
+    input_map.merge(&gamepad_input_map());
+    input_map
+}
+
+pub fn keyboard_input_map() -> InputMap<CarAction> {
     use CarAction::*;
     let mut input_map = InputMap::default();
 
-    // Keyboard mappings
-    input_map.insert(PushForward, KeyCode::ArrowUp);
-    input_map.insert(PushBackward, KeyCode::ArrowDown);
+    input_map.insert_axis(Throttle, VirtualAxis::new(KeyCode::ArrowDown, KeyCode::ArrowUp));
     input_map.insert(TurnLeft, KeyCode::ArrowLeft); // Keyboard button for turning left
     input_map.insert(TurnRight, KeyCode::ArrowRight); // Keyboard button for turning right
     input_map.insert(Boost, KeyCode::Space);
+    input_map.insert(Handbrake, KeyCode::ShiftLeft);
+    input_map.insert(Headlights, KeyCode::KeyH);
     input_map.insert(Reset, KeyCode::Digit1);
+    input_map.insert(Settings, KeyCode::Escape);
+    input_map.insert(CycleCamera, KeyCode::KeyC);
+    input_map.insert(LockTimeOfDay, KeyCode::KeyT);
+    input_map.insert(EarlierTimeOfDay, KeyCode::BracketLeft);
+    input_map.insert(LaterTimeOfDay, KeyCode::BracketRight);
+
+    input_map
+}
+
+/// Gamepad only, for split screen players who don't share the keyboard
+pub fn gamepad_input_map() -> InputMap<CarAction> {
+    use CarAction::*;
+    let mut input_map = InputMap::default();
 
-    // Gamepad mappings (matching old implementation)
-    input_map.insert(PushForward, GamepadButton::RightTrigger2);
-    input_map.insert(Boost, GamepadButton::LeftTrigger2);
+    // Analog triggers: right to accelerate, left to brake and reverse
+    input_map.insert_axis(Throttle, VirtualAxis::new(GamepadButton::LeftTrigger2, GamepadButton::RightTrigger2));
+    // Boost used to be the left trigger, with both triggers on the throttle it moved to the right bumper
+    input_map.insert(Boost, GamepadButton::RightTrigger);
+    input_map.insert(Handbrake, GamepadButton::East);
+    input_map.insert(Headlights, GamepadButton::DPadUp);
     input_map.insert(Reset, GamepadButton::North);
+    input_map.insert(Settings, GamepadButton::Start);
+    input_map.insert(CycleCamera, GamepadButton::Select);
+    input_map.insert(LockTimeOfDay, GamepadButton::DPadDown);
+    input_map.insert(EarlierTimeOfDay, GamepadButton::DPadLeft);
+    input_map.insert(LaterTimeOfDay, GamepadButton::DPadRight);
     
     // Gamepad right stick for turning (X axis) - matching old implementation
     input_map.insert_dual_axis(TurnAxis, GamepadStick::RIGHT);
@@ -1,11 +1,90 @@
+use std::f32::consts::TAU;
+
 use bevy::prelude::*;
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::{car::PlayerCar, graphics::GraphicsSettings, input::CarAction, state::GameState};
+
+pub struct LightingPlugin;
+
+impl Plugin for LightingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TimeOfDay>().add_systems(
+            Update,
+            (
+                control_time_of_day,
+                advance_time_of_day.after(control_time_of_day),
+                apply_time_of_day.after(advance_time_of_day),
+            )
+                .run_if(in_state(GameState::Running)),
+        );
+    }
+}
+
+/// Current time of day, drives the sun, moon, ambient light and sky color
+#[derive(Resource, Debug)]
+pub struct TimeOfDay {
+    /// Hour of the day in [0, 24)
+    pub hour: f32,
+    /// Real seconds for a full day
+    pub cycle_length: f32,
+    /// Stop the clock, e.g. to line up screenshots
+    pub locked: bool,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self {
+            // Start at night to match the original moonlit look
+            hour: 22.0,
+            cycle_length: 600.0,
+            locked: false,
+        }
+    }
+}
+
+impl TimeOfDay {
+    /// Sun height above the horizon in [-1, 1], 1 at noon
+    pub fn sun_elevation(&self) -> f32 {
+        ((self.hour - 6.0) / 24.0 * TAU).sin()
+    }
+
+    pub fn is_night(&self) -> bool {
+        self.sun_elevation() < -0.05
+    }
+}
+
+#[derive(Component)]
+pub struct Sun;
+
+#[derive(Component)]
+pub struct Moon;
+
+struct SkyKey {
+    hour: f32,
+    sky: Color,
+    ambient: Color,
+    ambient_brightness: f32,
+}
+
+// Sky and ambient colors through the day, interpolated between keys
+const SKY_KEYS: [SkyKey; 8] = [
+    SkyKey { hour: 0.0, sky: Color::srgb(0.0, 0.0, 0.0), ambient: Color::srgb(0.75, 0.75, 0.75), ambient_brightness: 1000.0 },
+    SkyKey { hour: 5.0, sky: Color::srgb(0.0, 0.0, 0.0), ambient: Color::srgb(0.75, 0.75, 0.75), ambient_brightness: 1000.0 },
+    SkyKey { hour: 6.5, sky: Color::srgb(0.55, 0.3, 0.25), ambient: Color::srgb(0.9, 0.7, 0.6), ambient_brightness: 800.0 },
+    SkyKey { hour: 9.0, sky: Color::srgb(0.45, 0.65, 0.9), ambient: Color::srgb(1.0, 0.95, 0.9), ambient_brightness: 1500.0 },
+    SkyKey { hour: 17.0, sky: Color::srgb(0.45, 0.65, 0.9), ambient: Color::srgb(1.0, 0.95, 0.9), ambient_brightness: 1500.0 },
+    SkyKey { hour: 19.0, sky: Color::srgb(0.6, 0.3, 0.2), ambient: Color::srgb(0.9, 0.6, 0.5), ambient_brightness: 800.0 },
+    SkyKey { hour: 20.5, sky: Color::srgb(0.0, 0.0, 0.0), ambient: Color::srgb(0.75, 0.75, 0.75), ambient_brightness: 1000.0 },
+    SkyKey { hour: 24.0, sky: Color::srgb(0.0, 0.0, 0.0), ambient: Color::srgb(0.75, 0.75, 0.75), ambient_brightness: 1000.0 },
+];
 
 pub fn setup(mut commands: Commands, mut ambient_light: ResMut<AmbientLight>) {
     // Update ambient light using ResMut
     ambient_light.brightness = 1000.0;
     ambient_light.color = Color::srgb(0.75, 0.75, 0.75); // Silver/gray tone (like limbo_pass)
     ambient_light.affects_lightmapped_meshes = true;
-    
+
     // Add a directional light (moon) for overall scene illumination with shadows
     commands.spawn((
         DirectionalLight {
@@ -17,5 +96,92 @@ pub fn setup(mut commands: Commands, mut ambient_light: ResMut<AmbientLight>) {
             ..default()
         },
         Transform::from_rotation(Quat::from_euler(EulerRot::XYZ, -0.5, -0.3, 0.0)), // Angle the moon
+        Moon,
     ));
+
+    // The sun starts below the horizon, apply_time_of_day turns it up at dawn
+    commands.spawn((
+        DirectionalLight {
+            color: Color::srgb(1.0, 0.95, 0.85), // Warm sunlight color
+            illuminance: 0.0,
+            shadows_enabled: false,
+            shadow_depth_bias: 0.02,
+            shadow_normal_bias: 0.8,
+            ..default()
+        },
+        Transform::default(),
+        Sun,
+    ));
+}
+
+fn control_time_of_day(action_state_query: Query<&ActionState<CarAction>, With<PlayerCar>>, mut time_of_day: ResMut<TimeOfDay>) {
+    let just_pressed = |action: CarAction| action_state_query.iter().any(|action_state| action_state.just_pressed(&action));
+
+    if just_pressed(CarAction::LockTimeOfDay) {
+        time_of_day.locked = !time_of_day.locked;
+        bevy::log::info!("Time of day {} at {:.1}h", if time_of_day.locked { "locked" } else { "unlocked" }, time_of_day.hour);
+    }
+
+    // Scrub through the day, handy for screenshots while locked
+    if just_pressed(CarAction::EarlierTimeOfDay) {
+        time_of_day.hour = (time_of_day.hour - 0.5).rem_euclid(24.0);
+    }
+    if just_pressed(CarAction::LaterTimeOfDay) {
+        time_of_day.hour = (time_of_day.hour + 0.5).rem_euclid(24.0);
+    }
+}
+
+fn advance_time_of_day(time: Res<Time>, mut time_of_day: ResMut<TimeOfDay>) {
+    if time_of_day.locked || time_of_day.cycle_length <= 0.0 {
+        return;
+    }
+
+    let hours_per_second = 24.0 / time_of_day.cycle_length;
+    time_of_day.hour = (time_of_day.hour + time.delta_secs() * hours_per_second).rem_euclid(24.0);
+}
+
+fn apply_time_of_day(
+    time_of_day: Res<TimeOfDay>,
+    graphics: Res<GraphicsSettings>,
+    mut ambient_light: ResMut<AmbientLight>,
+    mut clear_color: ResMut<ClearColor>,
+    mut sun_query: Query<(&mut DirectionalLight, &mut Transform), (With<Sun>, Without<Moon>)>,
+    mut moon_query: Query<(&mut DirectionalLight, &mut Transform), (With<Moon>, Without<Sun>)>,
+) {
+    // The sun travels east to west, tilted a little so it never passes straight overhead
+    let angle = (time_of_day.hour - 6.0) / 24.0 * TAU;
+    let sun_position = Vec3::new(angle.cos(), angle.sin(), 0.35).normalize();
+    let elevation = time_of_day.sun_elevation();
+
+    // Only the light above the horizon casts shadows, one shadow map is plenty for WebGL2
+    if let Ok((mut sun, mut transform)) = sun_query.single_mut() {
+        let daylight = elevation.max(0.0).sqrt();
+        // Redder near the horizon
+        sun.color = Color::srgb(1.0, 0.6, 0.35).mix(&Color::srgb(1.0, 0.95, 0.85), daylight);
+        sun.illuminance = 15000.0 * daylight;
+        sun.shadows_enabled = graphics.shadows && elevation > 0.0;
+        *transform = Transform::default().looking_to(-sun_position, Vec3::Z);
+    }
+
+    if let Ok((mut moon, mut transform)) = moon_query.single_mut() {
+        let moonlight = (-elevation).max(0.0).sqrt();
+        moon.illuminance = 10000.0 * moonlight;
+        moon.shadows_enabled = graphics.shadows && elevation <= 0.0;
+        *transform = Transform::default().looking_to(sun_position, Vec3::Z);
+    }
+
+    let next = SKY_KEYS
+        .iter()
+        .position(|key| key.hour > time_of_day.hour)
+        .unwrap_or(SKY_KEYS.len() - 1);
+    let (from, to) = (&SKY_KEYS[next.saturating_sub(1)], &SKY_KEYS[next]);
+    let t = if to.hour > from.hour {
+        (time_of_day.hour - from.hour) / (to.hour - from.hour)
+    } else {
+        0.0
+    };
+
+    clear_color.0 = from.sky.mix(&to.sky, t);
+    ambient_light.color = from.ambient.mix(&to.ambient, t);
+    ambient_light.brightness = from.ambient_brightness + (to.ambient_brightness - from.ambient_brightness) * t;
 }
@@ -1,19 +1,44 @@
-use bevy::{light::PointLightShadowMap, prelude::*, window::{PresentMode, WindowResolution}};
+use std::time::Duration;
+
+use bevy::{app::{PluginGroupBuilder, ScheduleRunnerPlugin}, prelude::*, render::{settings::{WgpuFeatures, WgpuSettings}, RenderPlugin}, window::{ExitCondition, PresentMode, WindowResolution}, winit::WinitPlugin};
 use bevy_kira_audio::AudioPlugin;
 use bevy_rapier3d::prelude::*;
 use leafwing_input_manager::prelude::*;
 use smooth_bevy_cameras::LookTransformPlugin;
 use crate::state::GameState;
 
+mod ai;
 mod assets;
 mod camera;
 mod car;
+mod car_audio;
+mod damage;
+mod fuel;
+mod graphics;
+mod gym;
+mod headlights;
+mod impacts;
 mod lighting;
 mod movement;
+mod net;
+mod pickups;
+mod recover;
+mod rng;
+mod rumble;
+mod run;
+mod save;
 mod scene;
+mod settings;
+mod split_screen;
 mod state;
+mod storage;
+mod synth;
+mod surface;
+mod tracks;
 mod input;
 mod theme; 
+mod touch;
+mod weather;
 
 #[derive(Component)]
 struct DriveScreen;
@@ -25,33 +50,16 @@ struct DriveButton;
 struct SetupInputEntity;
 
 fn main() {
+    let net_mode = net::NetMode::from_args();
+
     App::new()
-        .insert_resource(PointLightShadowMap { size: 2048 })
         .insert_resource(ClearColor(
             Color::srgb_u8(0x00, 0x00, 0x00), // Black
         ))
         .insert_resource(assets::SceneResource::default())
-        .add_plugins(DefaultPlugins
-            .set(AssetPlugin {
-                meta_check: bevy::asset::AssetMetaCheck::Never,
-                ..default()
-            })
-            .set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "desertcar".to_string(),
-                resolution: WindowResolution::new(1280, 720),
-                // Fifo doesn't work on WASM, use AutoVsync instead
-                present_mode: if cfg!(target_arch = "wasm32") {
-                    PresentMode::AutoVsync
-                } else {
-                    PresentMode::Fifo
-                },
-                // Make window fill the viewport on WASM
-                fit_canvas_to_parent: cfg!(target_arch = "wasm32"),
-                ..default()
-            }),
-            ..default()
-        }))
+        // The desert rolls its own sandstorms, pin a kind with WeatherSchedule::Fixed
+        .insert_resource(weather::WeatherSchedule::Random { seed: 0xD0_5E })
+        .add_plugins(default_plugins(net_mode.is_server()))
         .init_state::<GameState>()
         .add_plugins((
             LookTransformPlugin,
@@ -64,7 +72,6 @@ fn main() {
             spawn_setup_camera,
             lighting::setup,
             assets::load,
-            theme::load,
             spawn_drive_screen,
             spawn_setup_input_entity,
         ))
@@ -81,17 +88,100 @@ fn main() {
             scene::setup,
             camera::setup,
             car::spawn_controls_text,
-            theme::play,
-            theme::spawn_music_toggle,
-        ))
-        .add_systems(Update, (
-            theme::handle_music_toggle.run_if(in_state(GameState::Running)),
         ))
         .add_plugins(car::CarPlugin)
+        .add_plugins(ai::AiPlugin)
+        .add_plugins(pickups::PickupsPlugin)
+        .add_plugins(fuel::FuelPlugin)
+        .add_plugins(damage::DamagePlugin)
+        .add_plugins(recover::RecoverPlugin)
+        .add_plugins(split_screen::SplitScreenPlugin)
+        .add_plugins(lighting::LightingPlugin)
+        .add_plugins(headlights::HeadlightsPlugin)
+        .add_plugins(weather::WeatherPlugin)
+        .add_plugins(impacts::ImpactsPlugin)
+        .add_plugins(car_audio::CarAudioPlugin)
+        .add_plugins(rumble::RumblePlugin)
+        .add_plugins(run::RunPlugin)
+        .add_plugins(surface::SurfacePlugin)
+        .add_plugins(tracks::TracksPlugin)
+        .add_plugins(save::SavePlugin)
+        .add_plugins(settings::SettingsPlugin)
+        .add_plugins(graphics::GraphicsPlugin)
+        .add_plugins(theme::ThemePlugin)
+        .add_plugins(touch::TouchControlsPlugin)
+        .add_plugins(net::NetPlugin { mode: net_mode })
+        .add_plugins(gym::GymPlugin)
         // .add_plugins(RapierDebugRenderPlugin::default())
         .run();
 }
 
+fn default_plugins(headless: bool) -> PluginGroupBuilder {
+    let plugins = DefaultPlugins
+        .set(AssetPlugin {
+            meta_check: bevy::asset::AssetMetaCheck::Never,
+            ..default()
+        })
+        .set(render_plugin(headless));
+
+    if headless {
+        // A dedicated server has no window to drive the frames, step the simulation at 60 Hz instead
+        return plugins
+            .set(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                ..default()
+            })
+            .disable::<WinitPlugin>()
+            .add(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1.0 / 60.0)));
+    }
+
+    plugins.set(WindowPlugin {
+        primary_window: Some(Window {
+            title: "desertcar".to_string(),
+            resolution: WindowResolution::new(1280, 720),
+            // Fifo doesn't work on WASM, use AutoVsync instead
+            present_mode: if cfg!(target_arch = "wasm32") {
+                PresentMode::AutoVsync
+            } else {
+                PresentMode::Fifo
+            },
+            // Make window fill the viewport on WASM
+            fit_canvas_to_parent: cfg!(target_arch = "wasm32"),
+            ..default()
+        }),
+        ..default()
+    })
+}
+
+fn render_plugin(headless: bool) -> RenderPlugin {
+    // Nothing to draw on a dedicated server, don't ask for a GPU at all
+    if headless {
+        return RenderPlugin {
+            render_creation: WgpuSettings {
+                backends: None,
+                ..default()
+            }
+            .into(),
+            ..default()
+        };
+    }
+
+    // The wireframe overlay needs line polygon mode, which WebGL2 doesn't support
+    if cfg!(target_arch = "wasm32") {
+        return RenderPlugin::default();
+    }
+
+    RenderPlugin {
+        render_creation: WgpuSettings {
+            features: WgpuFeatures::POLYGON_MODE_LINE,
+            ..default()
+        }
+        .into(),
+        ..default()
+    }
+}
+
 fn spawn_setup_camera(mut commands: Commands) {
     commands.spawn(Camera3d::default());
 }
@@ -163,10 +253,10 @@ fn cleanup_drive_screen(mut commands: Commands, query: Query<Entity, With<DriveS
     }
 }
 
-fn spawn_setup_input_entity(mut commands: Commands) {
+fn spawn_setup_input_entity(mut commands: Commands, save_data: Res<save::SaveData>) {
     // Spawn a temporary entity with input map to handle gamepad input in Setup state
     commands.spawn((
-        input::default_input_map(),
+        input::player_input_map(save_data.key_bindings.as_ref()),
         SetupInputEntity,
     ));
 }
@@ -1,7 +1,7 @@
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 
-use crate::car::Car;
+use crate::{car::Car, damage::Damage, impacts::on_ground, surface::GroundSurface, weather::Weather};
 
 #[derive(Debug)]
 pub enum CarMovement {
@@ -9,6 +9,7 @@ pub enum CarMovement {
     PushBackward(f32),
     TurnLeft(f32),
     TurnRight(f32),
+    Handbrake,
 }
 
 impl CarMovement {
@@ -31,14 +32,34 @@ impl CarMovement {
 #[derive(Default, Component, Debug)]
 pub struct CarMovements(pub Vec<CarMovement>);
 
+/// Current drift state of a car, updated by `apply_movement`
+/// Other systems (scoring, audio, tire tracks) read this to react to slides
+#[derive(Default, Component, Debug)]
+pub struct Drift {
+    /// Signed angle between the car's heading and its horizontal velocity, in radians
+    pub angle: f32,
+    /// How long the current drift has lasted, in seconds
+    pub duration: f32,
+    pub is_drifting: bool,
+}
+
+// Distance from the car's center to the front and rear axles along its local x axis
+const AXLE_OFFSET: f32 = 2.5;
+// Below this horizontal speed the slip angle is too noisy to mean anything
+const MIN_SLIP_SPEED: f32 = 5.0;
+
 pub fn apply_movement(
-    mut car_query: Query<(Entity, &mut CarMovements, &mut Car, &GlobalTransform, &mut ExternalForce, &Velocity, &CollidingEntities)>,
+    mut car_query: Query<(Entity, &mut CarMovements, &mut Car, &mut Drift, &GlobalTransform, &mut ExternalForce, &Velocity, &CollidingEntities, Option<&GroundSurface>, Option<&Damage>)>,
+    time: Res<Time>,
+    weather: Option<Res<Weather>>,
+    sensor_query: Query<(), With<Sensor>>,
 ) {
-    if let Ok((_car_entity, mut car_movements, car, global_transform, mut rb_forces, rb_velocities, colliding_entities)) =
-        car_query.single_mut()
+    // Player and AI cars all drive through here, whoever filled in their movements
+    for (_car_entity, mut car_movements, car, mut drift, global_transform, mut rb_forces, rb_velocities, colliding_entities, ground_surface, damage) in
+        car_query.iter_mut()
     {
         // Check if car is on ground by checking if it has any collisions
-        let is_on_ground = !colliding_entities.is_empty();
+        let is_on_ground = on_ground(colliding_entities, &sensor_query);
         
         let mut forces = Vec3::new(0.0, 0.0, 0.0);
         let mut torques = Vec3::new(0.0, 0.0, 0.0);
@@ -49,13 +70,17 @@ pub fn apply_movement(
         } else {
             0.000001 // Minimal force in air
         };
+
+        let handbrake = car_movements.0.iter().any(|m| matches!(m, CarMovement::Handbrake));
+        // A battered car pushes and turns less hard
+        let (thrust_factor, steering_factor) = damage.map_or((1.0, 1.0), |damage| (damage.thrust_factor(), damage.steering_factor()));
         
         for car_movement in car_movements.0.iter() {
             // Apply forward/backward forces - reduce when in the air
-            forces += car_movement.as_lin_vec() * car.thrust * movement_multiplier;
+            forces += car_movement.as_lin_vec() * car.thrust * movement_multiplier * thrust_factor;
             // Apply turning torques - reduce when in the air
             let ang_vec = car_movement.as_ang_vec();
-            torques += ang_vec * car.thrust * movement_multiplier;
+            torques += ang_vec * car.thrust * movement_multiplier * steering_factor;
         }
 
         let local_to_global = global_transform.affine();
@@ -65,10 +90,50 @@ pub fn apply_movement(
         let rotation = global_transform.to_scale_rotation_translation().1;
         torques = rotation * torques;
 
+        // Storm winds push the car around whether it's on the ground or not
+        if let Some(weather) = &weather {
+            forces += weather.wind_force();
+        }
+
         let linvel: Vec3 = rb_velocities.linvel;
-        
-        // Apply normal drag - no special handling for upward movement
-        forces -= linvel * car.drag;
+
+        // Split horizontal velocity into the car's forward (local x) and sideways (local z) directions
+        let forward = (rotation * Vec3::X).with_y(0.0).normalize_or_zero();
+        let lateral = (rotation * Vec3::Z).with_y(0.0).normalize_or_zero();
+        let forward_speed = linvel.dot(forward);
+        let lateral_speed = linvel.dot(lateral);
+        let horizontal_speed = linvel.with_y(0.0).length();
+
+        let slip_angle = if horizontal_speed > MIN_SLIP_SPEED {
+            lateral_speed.atan2(forward_speed.abs())
+        } else {
+            0.0
+        };
+
+        if is_on_ground {
+            let surface = ground_surface.map(|g| g.surface).unwrap_or_default().properties();
+
+            // Rolling drag along the heading, the handbrake locks the rear wheels and adds braking
+            let longitudinal_drag = if handbrake { car.drag.x * 1.5 } else { car.drag.x };
+            forces -= forward * forward_speed * longitudinal_drag * surface.rolling_resistance;
+
+            // Tires hold full grip up to the peak slip angle, then fall off towards sliding grip
+            let grip = slip_grip(slip_angle.abs(), car.peak_slip_angle, car.sliding_grip) * surface.grip;
+            let rear_grip = if handbrake { grip * car.handbrake_grip } else { grip };
+
+            // Each axle resists half of the sideways motion at its own contact point,
+            // so losing rear grip lets the front swing the tail out
+            let front_force = -lateral * lateral_speed * car.drag.z * 0.5 * grip;
+            let rear_force = -lateral * lateral_speed * car.drag.z * 0.5 * rear_grip;
+            forces += front_force + rear_force;
+            torques += (forward * AXLE_OFFSET).cross(front_force);
+            torques += (-forward * AXLE_OFFSET).cross(rear_force);
+
+            forces.y -= linvel.y * car.drag.y;
+        } else {
+            // Apply normal drag - no special handling for upward movement
+            forces -= linvel * car.drag;
+        }
         
         let angvel: Vec3 = rb_velocities.angvel;
         // Apply angular drag - use much lower drag on Y axis (turning) to allow steering
@@ -78,10 +143,29 @@ pub fn apply_movement(
         rb_forces.force = forces;
         rb_forces.torque = torques;
 
+        drift.angle = slip_angle;
+        drift.is_drifting = is_on_ground && slip_angle.abs() > car.peak_slip_angle;
+        if drift.is_drifting {
+            drift.duration += time.delta_secs();
+        } else {
+            drift.duration = 0.0;
+        }
+
         car_movements.0.clear();
     }
 }
 
+/// Fraction of lateral grip left at a given slip angle (radians)
+fn slip_grip(slip_angle: f32, peak_slip_angle: f32, sliding_grip: f32) -> f32 {
+    if slip_angle <= peak_slip_angle {
+        1.0
+    } else {
+        // Fade out over another peak's worth of angle, then hold at sliding grip
+        let t = ((slip_angle - peak_slip_angle) / peak_slip_angle).min(1.0);
+        1.0 + (sliding_grip - 1.0) * t
+    }
+}
+
 
 
 pub fn _wrap_movement(mut _form_query: Query<(&Car, &mut Transform)>) {
@@ -102,4 +186,39 @@ pub fn _wrap_movement(mut _form_query: Query<(&Car, &mut Transform)>) {
     //         transform.translation = Vec3::new(0.0, 20.0, 0.0);
     //     }
     // }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEAK: f32 = 0.2;
+    const SLIDING: f32 = 0.35;
+
+    #[test]
+    fn full_grip_up_to_the_peak_slip_angle() {
+        assert_eq!(slip_grip(0.0, PEAK, SLIDING), 1.0);
+        assert_eq!(slip_grip(PEAK * 0.5, PEAK, SLIDING), 1.0);
+        assert_eq!(slip_grip(PEAK, PEAK, SLIDING), 1.0);
+    }
+
+    #[test]
+    fn grip_fades_to_sliding_grip_past_the_peak() {
+        let halfway = slip_grip(PEAK * 1.5, PEAK, SLIDING);
+        assert!((halfway - (1.0 + SLIDING) / 2.0).abs() < 1e-5);
+
+        let mut last = 1.0;
+        for step in 0..=10 {
+            let grip = slip_grip(PEAK * (1.0 + step as f32 / 10.0), PEAK, SLIDING);
+            assert!(grip <= last, "grip went back up at step {step}");
+            last = grip;
+        }
+        assert!((last - SLIDING).abs() < 1e-5);
+    }
+
+    #[test]
+    fn grip_holds_at_sliding_grip_when_sideways() {
+        for slip_angle in [PEAK * 2.0, PEAK * 5.0, std::f32::consts::FRAC_PI_2] {
+            assert!((slip_grip(slip_angle, PEAK, SLIDING) - SLIDING).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,566 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+};
+
+use bevy::{asset::LoadState, gltf::Gltf, prelude::*};
+use bevy_rapier3d::prelude::{RigidBody, Velocity};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ai::AiSettings,
+    assets::SceneResource,
+    car::{player_start, reset_car, Car, CarOut, CarRecovered, PlayerCar, SpawnPoint, StuckTimer},
+    input::{get_car_movement, DriveInput},
+    movement::{apply_movement, CarMovements},
+    recover::{recover_cars, Recovery},
+    scene::spawn_car,
+    state::GameState,
+};
+
+/// Networked play: `--server [port]` runs a headless authoritative server,
+/// `--connect host:port` joins one. Input goes up, car snapshots come down, as JSON over UDP
+pub struct NetPlugin {
+    pub mode: NetMode,
+}
+
+impl Plugin for NetPlugin {
+    fn build(&self, app: &mut App) {
+        match self.mode {
+            NetMode::Offline => {}
+            NetMode::Server { port } => {
+                let Some(socket) = bind(("0.0.0.0", port)) else {
+                    return;
+                };
+                bevy::log::info!("Server listening on port {}", port);
+                app.insert_resource(NetServer {
+                    socket,
+                    clients: HashMap::new(),
+                    next_id: 0,
+                    snapshot_timer: 0.0,
+                })
+                .add_systems(Update, start_when_loaded.run_if(in_state(GameState::Setup)))
+                .add_systems(
+                    Update,
+                    (
+                        assign_net_ids,
+                        server_receive,
+                        drive_net_cars.after(server_receive).before(apply_movement),
+                        reset_net_cars_when_out.after(recover_cars),
+                        drop_silent_clients,
+                        server_send_snapshots.after(apply_movement),
+                    )
+                        .run_if(in_state(GameState::Running)),
+                );
+            }
+            NetMode::Client { server } => {
+                let Some(socket) = bind(("0.0.0.0", 0)) else {
+                    return;
+                };
+                bevy::log::info!("Connecting to {}", server);
+                // The server drives the AI cars, everyone sees the same ones
+                app.insert_resource(AiSettings { drivers: Vec::new() })
+                    .insert_resource(NetClient {
+                        socket,
+                        server,
+                        id: None,
+                        sequence: 0,
+                        hello_timer: 0.0,
+                        history: VecDeque::new(),
+                    })
+                    .add_systems(
+                        Update,
+                        (
+                            client_send_input.after(get_car_movement),
+                            client_receive.after(client_send_input).before(apply_movement),
+                            interpolate_remote_cars.after(client_receive),
+                            drop_stale_remote_cars,
+                        )
+                            .run_if(in_state(GameState::Running)),
+                    )
+                    .add_systems(Last, client_say_goodbye);
+            }
+        }
+    }
+}
+
+pub const DEFAULT_PORT: u16 = 7878;
+// Snapshots go out at 20 Hz, interpolation covers the gaps
+const SNAPSHOT_INTERVAL: f32 = 0.05;
+// Remote cars are drawn this far in the past, so there are usually two snapshots to blend between
+const INTERPOLATION_DELAY: f32 = 0.1;
+// Clients and remote cars not heard from for this long are gone
+const TIMEOUT_SECONDS: f32 = 5.0;
+// Prediction errors smaller than this are left alone, larger than SNAP_DISTANCE are snapped straight away
+const CORRECTION_DEADBAND: f32 = 0.1;
+const SNAP_DISTANCE: f32 = 5.0;
+// Fraction of a prediction error corrected each snapshot, so corrections don't jerk the camera
+const CORRECTION_RATE: f32 = 0.2;
+// Predicted positions kept for comparing against snapshots, two seconds at 60 Hz. Anything older
+// will never be acknowledged
+const HISTORY_LENGTH: usize = 120;
+// Plenty for a snapshot of every car, UDP can't carry more than this anyway
+const MAX_DATAGRAM: usize = 65_507;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetMode {
+    Offline,
+    Server { port: u16 },
+    Client { server: SocketAddr },
+}
+
+impl NetMode {
+    pub fn from_args() -> Self {
+        // No sockets in the browser
+        if cfg!(target_arch = "wasm32") {
+            return Self::Offline;
+        }
+
+        let args: Vec<String> = std::env::args().collect();
+        if let Some(index) = args.iter().position(|arg| arg == "--server") {
+            let port = args.get(index + 1).and_then(|port| port.parse().ok()).unwrap_or(DEFAULT_PORT);
+            return Self::Server { port };
+        }
+        if let Some(index) = args.iter().position(|arg| arg == "--connect") {
+            let server = args
+                .get(index + 1)
+                .and_then(|address| address.to_socket_addrs().ok()?.next());
+            match server {
+                Some(server) => return Self::Client { server },
+                None => bevy::log::warn!("--connect needs a server address like 127.0.0.1:{}, playing offline", DEFAULT_PORT),
+            }
+        }
+        Self::Offline
+    }
+
+    pub fn is_server(&self) -> bool {
+        matches!(self, Self::Server { .. })
+    }
+
+    pub fn is_client(&self) -> bool {
+        matches!(self, Self::Client { .. })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum ClientMessage {
+    /// Sent until the server answers with Welcome
+    Hello,
+    Input { sequence: u32, input: DriveInput },
+    Bye,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum ServerMessage {
+    /// The id of the client's own car in snapshots
+    Welcome { id: u32 },
+    /// Every car in the world, with the last input sequence the server applied for this client
+    Snapshot { ack: u32, cars: Vec<CarState> },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct CarState {
+    id: u32,
+    position: [f32; 3],
+    rotation: [f32; 4],
+    linvel: [f32; 3],
+    angvel: [f32; 3],
+}
+
+/// Identifies a car across the network, the same on the server and every client
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetId(pub u32);
+
+fn bind(address: impl ToSocketAddrs) -> Option<UdpSocket> {
+    let socket = UdpSocket::bind(address)
+        .and_then(|socket| socket.set_nonblocking(true).map(|_| socket))
+        .map_err(|error| bevy::log::error!("Failed to open network socket, playing offline: {}", error))
+        .ok()?;
+    Some(socket)
+}
+
+/// Read every datagram waiting on the socket
+fn receive_all<T: for<'de> Deserialize<'de>>(socket: &UdpSocket) -> Vec<(SocketAddr, T)> {
+    let mut buffer = vec![0; MAX_DATAGRAM];
+    let mut messages = Vec::new();
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((length, from)) => match serde_json::from_slice(&buffer[..length]) {
+                Ok(message) => messages.push((from, message)),
+                Err(error) => bevy::log::debug!("Ignoring bad datagram from {}: {}", from, error),
+            },
+            Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+            Err(error) => {
+                bevy::log::debug!("Network receive failed: {}", error);
+                break;
+            }
+        }
+    }
+    messages
+}
+
+fn send<T: Serialize>(socket: &UdpSocket, to: SocketAddr, message: &T) {
+    let result = serde_json::to_vec(message)
+        .map_err(|error| error.to_string())
+        .and_then(|bytes| socket.send_to(&bytes, to).map_err(|error| error.to_string()));
+    if let Err(error) = result {
+        bevy::log::debug!("Network send to {} failed: {}", to, error);
+    }
+}
+
+// Server
+
+#[derive(Resource)]
+struct NetServer {
+    socket: UdpSocket,
+    clients: HashMap<SocketAddr, RemoteClient>,
+    next_id: u32,
+    snapshot_timer: f32,
+}
+
+struct RemoteClient {
+    id: u32,
+    car: Entity,
+    /// Place on the start line, freed up again when the client leaves
+    slot: usize,
+    last_sequence: u32,
+    last_heard: f32,
+}
+
+/// A car on the server driven by a connected client's input
+#[derive(Component, Debug)]
+struct NetDriver;
+
+/// Nobody presses drive on a dedicated server or for a gym agent, start as soon as the desert has loaded
+pub fn start_when_loaded(
+    asset_server: Res<AssetServer>,
+    scene_assets: Res<SceneResource>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if let LoadState::Loaded = asset_server.load_state(&scene_assets.handle) {
+        bevy::log::info!("Desert loaded, starting without the drive screen");
+        next_state.set(GameState::Running);
+    }
+}
+
+/// AI cars are simulated on the server, give them ids so clients can draw them
+fn assign_net_ids(mut commands: Commands, mut server: ResMut<NetServer>, car_query: Query<Entity, (With<Car>, Without<NetId>)>) {
+    for car in car_query.iter() {
+        commands.entity(car).insert(NetId(server.next_id));
+        server.next_id += 1;
+    }
+}
+
+fn server_receive(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut server: ResMut<NetServer>,
+    scene_assets: Res<SceneResource>,
+    assets_gltf: Res<Assets<Gltf>>,
+    mut input_query: Query<&mut DriveInput, With<NetDriver>>,
+) {
+    let now = time.elapsed_secs();
+    for (from, message) in receive_all::<ClientMessage>(&server.socket) {
+        match message {
+            ClientMessage::Hello => {
+                // Hellos repeat until the welcome gets through, only the first one gets a car
+                if !server.clients.contains_key(&from) {
+                    let Some(scenes_gltf) = assets_gltf.get(&scene_assets.handle) else {
+                        continue;
+                    };
+                    let id = server.next_id;
+                    server.next_id += 1;
+                    // Line up behind the start, beside the other clients, in the first place nobody
+                    // is in. Slot 0 is the server's own car
+                    let slot = (1..)
+                        .find(|slot| !server.clients.values().any(|client| client.slot == *slot))
+                        .unwrap_or(1);
+                    let start = player_start(slot);
+                    let car = spawn_car(&mut commands, scenes_gltf.named_scenes["CAR"].clone(), Transform::from_translation(start))
+                        .insert((NetId(id), NetDriver, DriveInput::default(), Recovery::default()))
+                        .id();
+                    server.clients.insert(from, RemoteClient { id, car, slot, last_sequence: 0, last_heard: now });
+                    bevy::log::info!("Client {} joined as car {}", from, id);
+                }
+                let id = server.clients[&from].id;
+                send(&server.socket, from, &ServerMessage::Welcome { id });
+            }
+            ClientMessage::Input { sequence, input } => {
+                let Some(client) = server.clients.get_mut(&from) else {
+                    continue;
+                };
+                client.last_heard = now;
+                // UDP can reorder, an older input than the last one applied is stale
+                if sequence <= client.last_sequence {
+                    continue;
+                }
+                client.last_sequence = sequence;
+                if let Ok(mut drive_input) = input_query.get_mut(client.car) {
+                    // A reset in a frame that got overtaken still counts
+                    let reset = drive_input.reset || input.reset;
+                    *drive_input = DriveInput { reset, ..input };
+                }
+            }
+            ClientMessage::Bye => {
+                if let Some(client) = server.clients.remove(&from) {
+                    bevy::log::info!("Client {} left", from);
+                    commands.entity(client.car).despawn();
+                }
+            }
+        }
+    }
+}
+
+fn drive_net_cars(mut car_query: Query<(&DriveInput, &mut CarMovements), With<NetDriver>>) {
+    // A reset is picked up by recover, which clears it once the car is back on its wheels
+    for (drive_input, mut movements) in car_query.iter_mut() {
+        drive_input.apply(&mut movements);
+    }
+}
+
+/// Clients' cars go back to a breadcrumb when they flip or fall off if the server has respawn or
+/// auto-recover on, otherwise back at their start, the server never stops
+fn reset_net_cars_when_out(
+    mut car_out: MessageReader<CarOut>,
+    mut car_recovered: MessageReader<CarRecovered>,
+    mut car_query: Query<(&mut Transform, &mut Velocity, &mut StuckTimer, &SpawnPoint), With<NetDriver>>,
+) {
+    let recovered: Vec<Entity> = car_recovered.read().map(|recovered| recovered.car).collect();
+    for out in car_out.read() {
+        if recovered.contains(&out.car) {
+            continue;
+        }
+        if let Ok((mut transform, mut velocity, mut stuck_timer, spawn_point)) = car_query.get_mut(out.car) {
+            reset_car(&mut transform, &mut velocity, &mut stuck_timer, spawn_point);
+        }
+    }
+}
+
+fn drop_silent_clients(mut commands: Commands, time: Res<Time>, mut server: ResMut<NetServer>) {
+    let now = time.elapsed_secs();
+    server.clients.retain(|address, client| {
+        let alive = now - client.last_heard < TIMEOUT_SECONDS;
+        if !alive {
+            bevy::log::info!("Client {} timed out", address);
+            commands.entity(client.car).despawn();
+        }
+        alive
+    });
+}
+
+fn server_send_snapshots(
+    time: Res<Time>,
+    mut server: ResMut<NetServer>,
+    car_query: Query<(&NetId, &Transform, &Velocity)>,
+) {
+    server.snapshot_timer -= time.delta_secs();
+    if server.snapshot_timer > 0.0 {
+        return;
+    }
+    server.snapshot_timer = SNAPSHOT_INTERVAL;
+
+    let cars: Vec<CarState> = car_query
+        .iter()
+        .map(|(id, transform, velocity)| CarState {
+            id: id.0,
+            position: transform.translation.to_array(),
+            rotation: transform.rotation.to_array(),
+            linvel: velocity.linvel.to_array(),
+            angvel: velocity.angvel.to_array(),
+        })
+        .collect();
+
+    for (address, client) in server.clients.iter() {
+        let snapshot = ServerMessage::Snapshot {
+            ack: client.last_sequence,
+            cars: cars.clone(),
+        };
+        send(&server.socket, *address, &snapshot);
+    }
+}
+
+// Client
+
+#[derive(Resource)]
+struct NetClient {
+    socket: UdpSocket,
+    server: SocketAddr,
+    /// Our car's id, once the server has welcomed us
+    id: Option<u32>,
+    sequence: u32,
+    hello_timer: f32,
+    /// Where our car was predicted to be as each input was sent, for checking against the server
+    history: VecDeque<(u32, Vec3)>,
+}
+
+/// Another client's car, or an AI car, drawn from server snapshots instead of simulated here
+#[derive(Component, Debug, Default)]
+struct RemoteCar {
+    /// Snapshots in the order they arrived, stamped with the local time they arrived
+    snapshots: VecDeque<(f32, CarState)>,
+}
+
+fn client_send_input(
+    time: Res<Time>,
+    mut client: ResMut<NetClient>,
+    car_query: Query<(&PlayerCar, &DriveInput, &Transform)>,
+) {
+    if client.id.is_none() {
+        client.hello_timer -= time.delta_secs();
+        if client.hello_timer <= 0.0 {
+            client.hello_timer = 1.0;
+            send(&client.socket, client.server, &ClientMessage::Hello);
+        }
+        return;
+    }
+
+    // Only the first player's car is networked
+    let Some((_, drive_input, transform)) = car_query.iter().find(|(player, ..)| player.0 == 0) else {
+        return;
+    };
+
+    client.sequence += 1;
+    let sequence = client.sequence;
+    send(&client.socket, client.server, &ClientMessage::Input { sequence, input: *drive_input });
+
+    client.history.push_back((sequence, transform.translation));
+    while client.history.len() > HISTORY_LENGTH {
+        client.history.pop_front();
+    }
+}
+
+fn client_receive(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut client: ResMut<NetClient>,
+    scene_assets: Res<SceneResource>,
+    assets_gltf: Res<Assets<Gltf>>,
+    mut own_car_query: Query<(&PlayerCar, &mut Transform, &mut Velocity), Without<RemoteCar>>,
+    mut remote_query: Query<(&NetId, &mut RemoteCar)>,
+) {
+    let now = time.elapsed_secs();
+    for (from, message) in receive_all::<ServerMessage>(&client.socket) {
+        if from != client.server {
+            continue;
+        }
+
+        match message {
+            ServerMessage::Welcome { id } => {
+                if client.id.is_none() {
+                    bevy::log::info!("Joined the server as car {}", id);
+                }
+                client.id = Some(id);
+            }
+            ServerMessage::Snapshot { ack, cars } => {
+                let Some(own_id) = client.id else {
+                    continue;
+                };
+
+                let mut remote_cars: HashMap<u32, Mut<RemoteCar>> =
+                    remote_query.iter_mut().map(|(id, remote)| (id.0, remote)).collect();
+
+                for state in cars {
+                    if state.id == own_id {
+                        if let Some((_, mut transform, mut velocity)) =
+                            own_car_query.iter_mut().find(|(player, ..)| player.0 == 0)
+                        {
+                            smooth_prediction_error(&mut client, ack, &state, &mut transform, &mut velocity);
+                        }
+                    } else if let Some(remote) = remote_cars.get_mut(&state.id) {
+                        remote.snapshots.push_back((now, state));
+                    } else if let Some(scenes_gltf) = assets_gltf.get(&scene_assets.handle) {
+                        // New to us, the server moves it so physics here must not
+                        let transform = Transform::from_translation(Vec3::from_array(state.position))
+                            .with_rotation(Quat::from_array(state.rotation));
+                        let mut remote = RemoteCar::default();
+                        remote.snapshots.push_back((now, state));
+                        spawn_car(&mut commands, scenes_gltf.named_scenes["CAR"].clone(), transform)
+                            .insert((RigidBody::KinematicPositionBased, NetId(state.id), remote));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Check our predicted car against where the server says it is, and ease it back if it drifted.
+/// This is error smoothing, not a full reconcile: the unacknowledged inputs aren't replayed, since
+/// Rapier can't step one car on its own, so rotation and velocity are only taken from the server
+/// once the error is past SNAP_DISTANCE
+fn smooth_prediction_error(client: &mut NetClient, ack: u32, state: &CarState, transform: &mut Transform, velocity: &mut Velocity) {
+    // Inputs up to the acknowledged one are settled, what we predicted for it is what to compare
+    let mut predicted = None;
+    while let Some((sequence, position)) = client.history.front().copied() {
+        if sequence > ack {
+            break;
+        }
+        client.history.pop_front();
+        if sequence == ack {
+            predicted = Some(position);
+        }
+    }
+    let Some(predicted) = predicted else {
+        return;
+    };
+
+    let error = Vec3::from_array(state.position) - predicted;
+    if error.length() > SNAP_DISTANCE {
+        // Too far off to ease back, take the server's word for everything
+        transform.translation = Vec3::from_array(state.position);
+        transform.rotation = Quat::from_array(state.rotation);
+        velocity.linvel = Vec3::from_array(state.linvel);
+        velocity.angvel = Vec3::from_array(state.angvel);
+        client.history.clear();
+    } else if error.length() > CORRECTION_DEADBAND {
+        let correction = error * CORRECTION_RATE;
+        transform.translation += correction;
+        // Predictions still waiting on the server were made before this correction
+        for (_, position) in client.history.iter_mut() {
+            *position += correction;
+        }
+    }
+}
+
+fn interpolate_remote_cars(time: Res<Time>, mut remote_query: Query<(&mut RemoteCar, &mut Transform, &mut Velocity)>) {
+    let render_time = time.elapsed_secs() - INTERPOLATION_DELAY;
+
+    for (mut remote, mut transform, mut velocity) in remote_query.iter_mut() {
+        // Keep one snapshot from before the render time to blend from
+        while remote.snapshots.len() > 2 && remote.snapshots[1].0 <= render_time {
+            remote.snapshots.pop_front();
+        }
+
+        let (from_time, from) = remote.snapshots[0];
+        let (to_time, to) = remote.snapshots.get(1).copied().unwrap_or((from_time, from));
+        // Hold the newest snapshot rather than guess past it
+        let t = if to_time > from_time {
+            ((render_time - from_time) / (to_time - from_time)).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+
+        transform.translation = Vec3::from_array(from.position).lerp(Vec3::from_array(to.position), t);
+        transform.rotation = Quat::from_array(from.rotation).slerp(Quat::from_array(to.rotation), t);
+        // Dust, tracks and sounds read the velocity
+        velocity.linvel = Vec3::from_array(from.linvel).lerp(Vec3::from_array(to.linvel), t);
+    }
+}
+
+/// Cars the server stopped sending have left
+fn drop_stale_remote_cars(mut commands: Commands, time: Res<Time>, remote_query: Query<(Entity, &RemoteCar)>) {
+    let now = time.elapsed_secs();
+    for (entity, remote) in remote_query.iter() {
+        let last_heard = remote.snapshots.back().map(|(time, _)| *time).unwrap_or(0.0);
+        if now - last_heard > TIMEOUT_SECONDS {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+fn client_say_goodbye(mut app_exit: MessageReader<AppExit>, client: Res<NetClient>) {
+    // Saves the server waiting for the timeout
+    if app_exit.read().next().is_some() && client.id.is_some() {
+        send(&client.socket, client.server, &ClientMessage::Bye);
+    }
+}
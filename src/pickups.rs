@@ -0,0 +1,486 @@
+use std::collections::HashSet;
+
+use bevy::{
+    gltf::{Gltf, GltfNode},
+    light::NotShadowCaster,
+    prelude::*,
+};
+use bevy_kira_audio::{AudioChannel, AudioControl, AudioSource};
+use bevy_rapier3d::prelude::{ActiveEvents, Collider, CollisionEvent, QueryFilter, ReadRapierContext, Sensor};
+
+use crate::{
+    ai::RacingLine,
+    assets::SceneResource,
+    camera::PlayerCamera,
+    car::{PlayerCar, PlayerOut},
+    rng::Rng,
+    run::RunStats,
+    save::SaveData,
+    settings::SfxChannel,
+    split_screen::{spawn_player_text_huds, HudLine},
+    state::GameState,
+    synth,
+};
+
+pub struct PickupsPlugin;
+
+impl Plugin for PickupsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<PickupCollected>()
+            .add_systems(
+                OnEnter(GameState::Running),
+                (setup_pickup_assets, spawn_pickups.after(setup_pickup_assets)),
+            )
+            .add_systems(
+                Update,
+                (
+                    settle_pickups,
+                    animate_pickups.after(settle_pickups),
+                    collect_pickups,
+                    play_pickup_effects.after(collect_pickups),
+                    boost_from_pickups.after(collect_pickups),
+                    tick_orb_boosts.after(boost_from_pickups),
+                    spawn_pickup_huds,
+                    update_pickup_huds.after(collect_pickups).after(spawn_pickup_huds),
+                )
+                    .run_if(in_state(GameState::Running)),
+            )
+            // Sparks finish flying while the game over screen is up
+            .add_systems(Update, update_sparks.run_if(resource_exists::<PickupAssets>))
+            // Everything comes back for the next run
+            .add_systems(OnExit(GameState::GameOver), despawn_pickups);
+    }
+}
+
+// Completion is saved per map, there's only the one so far
+const MAP_NAME: &str = "desert";
+// Without markers in the glTF, pickups are scattered along the racing line from this seed
+const PICKUP_SEED: u64 = 0x91C4_B0B5;
+const SCATTERED_PICKUPS: usize = 40;
+// How far either side of the racing line a scattered pickup can land
+const SCATTER_WIDTH: f32 = 40.0;
+// Pickups float this far above the ground, bobbing this much either way
+const HOVER_HEIGHT: f32 = 1.8;
+const BOB_HEIGHT: f32 = 0.4;
+const SPIN_SPEED: f32 = 1.5;
+const PICKUP_RADIUS: f32 = 2.5;
+const SPARKS_PER_PICKUP: usize = 16;
+const SPARK_LIFETIME: f32 = 0.8;
+// A boost orb boosts the car as if the button were held, for this long
+const ORB_BOOST_SECONDS: f32 = 3.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickupKind {
+    FuelCan,
+    BoostOrb,
+    /// Only for completion, finding one does nothing else
+    Relic,
+    RepairKit,
+}
+
+impl PickupKind {
+    pub const ALL: [PickupKind; 4] = [PickupKind::FuelCan, PickupKind::BoostOrb, PickupKind::Relic, PickupKind::RepairKit];
+
+    /// glTF empties named PICKUP_FUEL, PICKUP_BOOST, PICKUP_RELIC or PICKUP_REPAIR (with any suffix) place pickups by hand
+    fn from_marker(name: &str) -> Option<Self> {
+        let kind = name.strip_prefix("PICKUP_")?;
+        Self::ALL.into_iter().find(|k| kind.starts_with(k.marker()))
+    }
+
+    fn marker(&self) -> &'static str {
+        match self {
+            Self::FuelCan => "FUEL",
+            Self::BoostOrb => "BOOST",
+            Self::Relic => "RELIC",
+            Self::RepairKit => "REPAIR",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Self::FuelCan => Color::srgb(0.9, 0.25, 0.15),
+            Self::BoostOrb => Color::srgb(0.2, 0.7, 1.0),
+            Self::Relic => Color::srgb_u8(0xAB, 0x69, 0xE7),
+            Self::RepairKit => Color::srgb(0.3, 0.85, 0.35),
+        }
+    }
+
+    /// Rarer finds ring higher
+    fn pitch(&self) -> f64 {
+        match self {
+            Self::FuelCan => 0.8,
+            Self::BoostOrb => 1.0,
+            Self::Relic => 1.3,
+            Self::RepairKit => 0.9,
+        }
+    }
+}
+
+/// A player's car drove through a pickup
+#[derive(Message, Debug, Clone, Copy)]
+pub struct PickupCollected {
+    pub car: Entity,
+    pub kind: PickupKind,
+    pub position: Vec3,
+}
+
+/// Seconds left on a boost orb's free boost
+#[derive(Component, Debug)]
+pub struct OrbBoost(pub f32);
+
+#[derive(Component, Debug)]
+pub struct Pickup {
+    /// Stable across runs and sessions, the same seed or markers always give the same ids
+    pub id: u32,
+    pub kind: PickupKind,
+    /// Resting point, the pickup bobs around it
+    base: Vec3,
+    /// Scattered pickups wait for the terrain collider before they know how high the ground is
+    settled: bool,
+    phase: f32,
+}
+
+/// How many pickups this map has, for completion
+#[derive(Resource, Debug)]
+struct PickupField {
+    total: u32,
+}
+
+#[derive(Resource)]
+struct PickupAssets {
+    meshes: Vec<Handle<Mesh>>,
+    // Indexed by `PickupKind as usize`, like the meshes
+    materials: Vec<Handle<StandardMaterial>>,
+    spark_mesh: Handle<Mesh>,
+    chime: Handle<AudioSource>,
+    rng: Rng,
+}
+
+#[derive(Component, Debug)]
+struct Spark {
+    velocity: Vec3,
+    age: f32,
+}
+
+/// A player's finds this run and the map's completion, in their half of the screen when it's split
+#[derive(Component, Clone)]
+struct PickupHudText;
+
+fn setup_pickup_assets(
+    mut commands: Commands,
+    existing_assets: Option<Res<PickupAssets>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut audio_sources: ResMut<Assets<AudioSource>>,
+) {
+    // Shared across runs, only build them once
+    if existing_assets.is_some() {
+        return;
+    }
+
+    let pickup_meshes = PickupKind::ALL
+        .iter()
+        .map(|kind| match kind {
+            PickupKind::FuelCan => meshes.add(Cuboid::new(0.9, 1.3, 0.6)),
+            PickupKind::BoostOrb => meshes.add(Sphere::new(0.7).mesh().uv(16, 12)),
+            PickupKind::Relic => meshes.add(Torus::new(0.45, 0.9)),
+            PickupKind::RepairKit => meshes.add(Capsule3d::new(0.35, 1.0)),
+        })
+        .collect();
+    let pickup_materials = PickupKind::ALL
+        .iter()
+        .map(|kind| {
+            // Glow a little so they stand out against the sand, and at night
+            materials.add(StandardMaterial {
+                base_color: kind.color(),
+                emissive: kind.color().to_linear() * 2.0,
+                ..default()
+            })
+        })
+        .collect();
+
+    commands.insert_resource(PickupAssets {
+        meshes: pickup_meshes,
+        materials: pickup_materials,
+        spark_mesh: meshes.add(Sphere::new(0.15).mesh().uv(6, 4)),
+        chime: audio_sources.add(synth::audio_source(synth::chime())),
+        rng: Rng::new(PICKUP_SEED),
+    });
+}
+
+fn spawn_pickups(
+    mut commands: Commands,
+    pickup_assets: Res<PickupAssets>,
+    scene_assets: Res<SceneResource>,
+    assets_gltf: Res<Assets<Gltf>>,
+    gltf_nodes: Res<Assets<GltfNode>>,
+    racing_line: Res<RacingLine>,
+    pickup_query: Query<(), With<Pickup>>,
+) {
+    // Still there from before the game over, or despawned and due again
+    if !pickup_query.is_empty() {
+        return;
+    }
+
+    // Hand placed markers win, sorted by name so the ids don't depend on load order
+    let mut markers: Vec<(String, PickupKind, Vec3)> = assets_gltf
+        .get(&scene_assets.handle)
+        .map(|gltf| {
+            gltf.named_nodes
+                .iter()
+                .filter_map(|(name, handle)| {
+                    let kind = PickupKind::from_marker(name)?;
+                    let node = gltf_nodes.get(handle)?;
+                    Some((name.to_string(), kind, node.transform.translation))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    markers.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let placements: Vec<(PickupKind, Vec3, bool)> = if markers.is_empty() {
+        let mut rng = Rng::new(PICKUP_SEED);
+        (0..SCATTERED_PICKUPS)
+            .map(|_| {
+                let t = rng.range(0.0, racing_line.control_points() as f32);
+                let along = racing_line.sample(t);
+                let tangent = racing_line.tangent(t);
+                let side = Vec2::new(-tangent.y, tangent.x) * rng.range(-SCATTER_WIDTH, SCATTER_WIDTH);
+                let point = along + side;
+                // Mostly fuel, some boost, a few repair kits, the odd relic
+                let roll = rng.next_f32();
+                let kind = if roll < 0.1 {
+                    PickupKind::Relic
+                } else if roll < 0.5 {
+                    PickupKind::BoostOrb
+                } else if roll < 0.6 {
+                    PickupKind::RepairKit
+                } else {
+                    PickupKind::FuelCan
+                };
+                (kind, Vec3::new(point.x, 0.0, point.y), false)
+            })
+            .collect()
+    } else {
+        markers.into_iter().map(|(_, kind, position)| (kind, position, true)).collect()
+    };
+
+    let mut rng = Rng::new(PICKUP_SEED ^ 1);
+    for (id, (kind, position, settled)) in placements.iter().enumerate() {
+        commands.spawn((
+            Mesh3d(pickup_assets.meshes[*kind as usize].clone()),
+            MeshMaterial3d(pickup_assets.materials[*kind as usize].clone()),
+            Transform::from_translation(*position),
+            if *settled { Visibility::Inherited } else { Visibility::Hidden },
+            NotShadowCaster,
+            // Cars drive straight through, the sensor only reports the overlap
+            Collider::ball(PICKUP_RADIUS),
+            Sensor,
+            ActiveEvents::COLLISION_EVENTS,
+            Pickup {
+                id: id as u32,
+                kind: *kind,
+                base: *position,
+                settled: *settled,
+                phase: rng.range(0.0, std::f32::consts::TAU),
+            },
+        ));
+    }
+
+    commands.insert_resource(PickupField {
+        total: placements.len() as u32,
+    });
+    bevy::log::info!("Placed {} pickups", placements.len());
+}
+
+/// Drop scattered pickups onto the ground once there's ground to find
+fn settle_pickups(
+    read_rapier_context: ReadRapierContext,
+    mut pickup_query: Query<(&mut Pickup, &mut Visibility)>,
+) {
+    let Ok(rapier_context) = read_rapier_context.single() else {
+        return;
+    };
+
+    for (mut pickup, mut visibility) in pickup_query.iter_mut() {
+        if pickup.settled {
+            continue;
+        }
+        let origin = Vec3::new(pickup.base.x, 500.0, pickup.base.z);
+        let filter = QueryFilter::default().exclude_sensors();
+        // No hit means the terrain collider isn't in yet, try again next frame
+        if let Some((_entity, distance)) = rapier_context.cast_ray(origin, Vec3::NEG_Y, 1000.0, true, filter) {
+            pickup.base.y = origin.y - distance + HOVER_HEIGHT;
+            pickup.settled = true;
+            *visibility = Visibility::Inherited;
+        }
+    }
+}
+
+fn animate_pickups(time: Res<Time>, mut pickup_query: Query<(&Pickup, &mut Transform)>) {
+    let t = time.elapsed_secs();
+    for (pickup, mut transform) in pickup_query.iter_mut() {
+        if !pickup.settled {
+            continue;
+        }
+        transform.translation = pickup.base + Vec3::Y * (t * 2.0 + pickup.phase).sin() * BOB_HEIGHT;
+        transform.rotation = Quat::from_rotation_y(t * SPIN_SPEED + pickup.phase);
+    }
+}
+
+fn collect_pickups(
+    mut commands: Commands,
+    mut collision_events: MessageReader<CollisionEvent>,
+    pickup_query: Query<(&Pickup, &Transform)>,
+    mut car_query: Query<&mut RunStats, (With<PlayerCar>, Without<PlayerOut>)>,
+    mut save_data: ResMut<SaveData>,
+    mut pickup_collected: MessageWriter<PickupCollected>,
+) {
+    // Both colliders can report the same overlap, a pickup only counts once
+    let mut collected = HashSet::new();
+
+    for event in collision_events.read() {
+        let CollisionEvent::Started(collider1, collider2, _) = *event else {
+            continue;
+        };
+
+        for (pickup_entity, car) in [(collider1, collider2), (collider2, collider1)] {
+            // AI cars drive straight through
+            let (Ok((pickup, transform)), Ok(mut stats)) = (pickup_query.get(pickup_entity), car_query.get_mut(car)) else {
+                continue;
+            };
+            if !pickup.settled || !collected.insert(pickup_entity) {
+                continue;
+            }
+
+            stats.pickups += 1;
+            // The save is written whenever it changes, so leave it alone for a repeat find
+            let already_found = save_data
+                .collected_pickups
+                .get(MAP_NAME)
+                .is_some_and(|found| found.contains(&pickup.id));
+            if !already_found {
+                save_data.collected_pickups.entry(MAP_NAME.to_string()).or_default().insert(pickup.id);
+            }
+
+            pickup_collected.write(PickupCollected {
+                car,
+                kind: pickup.kind,
+                position: transform.translation,
+            });
+            commands.entity(pickup_entity).despawn();
+        }
+    }
+}
+
+fn play_pickup_effects(
+    mut commands: Commands,
+    mut pickup_collected: MessageReader<PickupCollected>,
+    mut pickup_assets: ResMut<PickupAssets>,
+    sfx_channel: Res<AudioChannel<SfxChannel>>,
+) {
+    for collected in pickup_collected.read() {
+        sfx_channel
+            .play(pickup_assets.chime.clone())
+            .with_volume(synth::decibels(0.7))
+            .with_playback_rate(collected.kind.pitch());
+
+        // A burst of sparks in the pickup's colour
+        let material = pickup_assets.materials[collected.kind as usize].clone();
+        let mesh = pickup_assets.spark_mesh.clone();
+        for _ in 0..SPARKS_PER_PICKUP {
+            let rng = &mut pickup_assets.rng;
+            let direction = Vec3::new(rng.range(-1.0, 1.0), rng.range(0.3, 1.0), rng.range(-1.0, 1.0)).normalize_or_zero();
+            let velocity = direction * rng.range(4.0, 10.0);
+            commands.spawn((
+                Mesh3d(mesh.clone()),
+                MeshMaterial3d(material.clone()),
+                Transform::from_translation(collected.position),
+                NotShadowCaster,
+                Spark { velocity, age: 0.0 },
+            ));
+        }
+    }
+}
+
+fn boost_from_pickups(mut commands: Commands, mut pickup_collected: MessageReader<PickupCollected>) {
+    for collected in pickup_collected.read() {
+        // Another orb starts the clock over rather than adding to it
+        if collected.kind == PickupKind::BoostOrb {
+            commands.entity(collected.car).insert(OrbBoost(ORB_BOOST_SECONDS));
+        }
+    }
+}
+
+fn tick_orb_boosts(mut commands: Commands, time: Res<Time>, mut boost_query: Query<(Entity, &mut OrbBoost)>) {
+    for (car, mut boost) in boost_query.iter_mut() {
+        boost.0 -= time.delta_secs();
+        if boost.0 <= 0.0 {
+            commands.entity(car).remove::<OrbBoost>();
+        }
+    }
+}
+
+fn update_sparks(mut commands: Commands, time: Res<Time>, mut spark_query: Query<(Entity, &mut Spark, &mut Transform)>) {
+    let dt = time.delta_secs();
+    for (entity, mut spark, mut transform) in spark_query.iter_mut() {
+        spark.age += dt;
+        if spark.age >= SPARK_LIFETIME {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        transform.translation += spark.velocity * dt;
+        spark.velocity.y -= 15.0 * dt;
+        transform.scale = Vec3::splat(1.0 - spark.age / SPARK_LIFETIME);
+    }
+}
+
+fn spawn_pickup_huds(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    camera_query: Query<(Entity, &PlayerCamera)>,
+    hud_query: Query<(&HudLine, &ChildOf), With<PickupHudText>>,
+) {
+    let node = Node {
+        width: Val::Percent(100.),
+        position_type: PositionType::Absolute,
+        justify_content: JustifyContent::Center,
+        top: Val::Px(10.),
+        ..default()
+    };
+    spawn_player_text_huds(&mut commands, &asset_server, &camera_query, &hud_query, node, Color::srgb(0.9, 0.9, 0.9), PickupHudText);
+}
+
+fn update_pickup_huds(
+    field: Option<Res<PickupField>>,
+    save_data: Res<SaveData>,
+    car_query: Query<(&PlayerCar, &RunStats)>,
+    mut text_query: Query<(&mut Text, &HudLine), With<PickupHudText>>,
+) {
+    let Some(field) = field else {
+        return;
+    };
+    // Completion is shared, every player drives the same desert
+    let found = save_data.collected_pickups.get(MAP_NAME).map_or(0, |found| found.len()) as u32;
+    let completion = if field.total > 0 { found.min(field.total) * 100 / field.total } else { 100 };
+
+    for (mut text, hud) in text_query.iter_mut() {
+        let this_run = car_query
+            .iter()
+            .find(|(player, _)| player.0 == hud.player)
+            .map_or(0, |(_, stats)| stats.pickups);
+        let label = format!("pickups {}   {} {}%", this_run, MAP_NAME, completion);
+        if text.0 != label {
+            text.0 = label;
+        }
+    }
+}
+
+fn despawn_pickups(mut commands: Commands, pickup_query: Query<Entity, With<Pickup>>, boost_query: Query<Entity, With<OrbBoost>>) {
+    for entity in pickup_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    for car in boost_query.iter() {
+        commands.entity(car).remove::<OrbBoost>();
+    }
+}
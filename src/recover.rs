@@ -0,0 +1,300 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::{CollidingEntities, QueryFilter, ReadRapierContext, Sensor, Velocity};
+
+use crate::{
+    camera::PlayerCamera,
+    car::{check_game_over, check_stuck, end_run_when_player_out, CarOut, CarRecovered, PlayerCar, PlayerOut, StuckTimer},
+    impacts::on_ground,
+    input::DriveInput,
+    movement::apply_movement,
+    run::RunEndReason,
+    settings::GameplaySettings,
+    split_screen::{spawn_player_text_huds, HudLine},
+    state::GameState,
+};
+
+/// Puts cars back on their wheels somewhere safe from the trail of breadcrumbs they leave. With
+/// respawn or auto-recover turned on, a car that flips or falls off the map goes back to its latest
+/// or nearest crumb a few times a run. The reset button always uses the nearest
+pub struct RecoverPlugin;
+
+impl Plugin for RecoverPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RecoveryAllowed>()
+            .add_systems(OnEnter(GameState::Running), reset_recovery)
+            .add_systems(
+                Update,
+                (
+                    drop_breadcrumbs.after(apply_movement),
+                    reset_on_request.after(apply_movement),
+                    recover_cars
+                        .after(check_stuck)
+                        .after(check_game_over)
+                        .before(end_run_when_player_out),
+                    spawn_recovery_huds,
+                    update_recovery_huds.after(recover_cars).after(spawn_recovery_huds),
+                )
+                    .run_if(in_state(GameState::Running)),
+            );
+    }
+}
+
+pub const RECOVERIES_PER_RUN: u32 = 3;
+// A crumb is dropped at most this often, and only once the car has moved this far (m) from the
+// last one, so idling in one spot doesn't push the older crumbs out
+const CRUMB_INTERVAL: f32 = 0.25;
+const CRUMB_SPACING: f32 = 4.0;
+const MAX_CRUMBS: usize = 30;
+// Crumbs from the last second lead into whatever went wrong, don't go back there
+const MIN_CRUMB_AGE: f32 = 1.0;
+// Upright enough to count as safe, the car's up against world up
+const UPRIGHT: f32 = 0.9;
+// Dropped from this high so the car settles onto the ground rather than into it
+const LIFT_HEIGHT: f32 = 2.5;
+// Seconds after a respawn where the car can't go out or take damage
+const RESPAWN_GRACE: f32 = 2.0;
+
+/// Whether the respawn and auto-recover settings apply at all. The gym turns it off so an episode
+/// ends when the car goes out, whatever the player has saved
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct RecoveryAllowed(pub bool);
+
+impl Default for RecoveryAllowed {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+/// A pose where the car was on its wheels and touching the ground
+#[derive(Debug, Clone, Copy)]
+struct Breadcrumb {
+    position: Vec3,
+    rotation: Quat,
+    /// Elapsed game time when it was dropped
+    time: f32,
+}
+
+/// The trail of safe poses a car left behind, and how many recoveries it has used this run
+#[derive(Component, Debug, Default)]
+pub struct Recovery {
+    breadcrumbs: VecDeque<Breadcrumb>,
+    crumb_timer: f32,
+    pub used: u32,
+}
+
+/// Recoveries a player has left, in their half of the screen when it's split
+#[derive(Component, Clone)]
+struct RecoveryHudText;
+
+fn reset_recovery(mut recovery_query: Query<&mut Recovery>) {
+    for mut recovery in recovery_query.iter_mut() {
+        *recovery = Recovery::default();
+    }
+}
+
+fn drop_breadcrumbs(
+    time: Res<Time>,
+    mut car_query: Query<(&mut Recovery, &Transform, &CollidingEntities, &StuckTimer), Without<PlayerOut>>,
+    sensor_query: Query<(), With<Sensor>>,
+) {
+    for (mut recovery, transform, colliding_entities, stuck_timer) in car_query.iter_mut() {
+        recovery.crumb_timer -= time.delta_secs();
+        if recovery.crumb_timer > 0.0 {
+            continue;
+        }
+
+        // Still settling after a respawn, or in the air, or tipping over
+        let upright = (transform.rotation * Vec3::Y).dot(Vec3::Y) > UPRIGHT;
+        if stuck_timer.invulnerable() || !upright || !on_ground(colliding_entities, &sensor_query) {
+            continue;
+        }
+        if recovery
+            .breadcrumbs
+            .back()
+            .is_some_and(|last| last.position.distance(transform.translation) < CRUMB_SPACING)
+        {
+            continue;
+        }
+
+        recovery.crumb_timer = CRUMB_INTERVAL;
+        recovery.breadcrumbs.push_back(Breadcrumb {
+            position: transform.translation,
+            rotation: transform.rotation,
+            time: time.elapsed_secs(),
+        });
+        while recovery.breadcrumbs.len() > MAX_CRUMBS {
+            recovery.breadcrumbs.pop_front();
+        }
+    }
+}
+
+/// The most recent breadcrumb that's old enough to be clear of the trouble
+fn latest_crumb(recovery: &Recovery, now: f32) -> Option<&Breadcrumb> {
+    recovery
+        .breadcrumbs
+        .iter()
+        .rev()
+        .find(|crumb| now - crumb.time >= MIN_CRUMB_AGE)
+        // A run that went wrong straight away only has fresh crumbs, the oldest is the best there is
+        .or_else(|| recovery.breadcrumbs.front())
+}
+
+/// The breadcrumb closest to where the car is now, for putting it right without sending it back.
+/// Skips the last second like `latest_crumb`
+fn closest_crumb(recovery: &Recovery, current: Vec3, now: f32) -> Option<&Breadcrumb> {
+    recovery
+        .breadcrumbs
+        .iter()
+        .rev()
+        .filter(|crumb| now - crumb.time >= MIN_CRUMB_AGE)
+        // Ties go to the most recent
+        .min_by(|a, b| a.position.distance_squared(current).total_cmp(&b.position.distance_squared(current)))
+        .or_else(|| recovery.breadcrumbs.front())
+}
+
+/// Level with the given heading and lifted clear of the ground under the position
+fn grounded_pose(position: Vec3, rotation: Quat, car: Entity, read_rapier_context: &ReadRapierContext) -> Option<Transform> {
+    // Only the heading, the car comes back level
+    let (yaw, _, _) = rotation.to_euler(EulerRot::YXZ);
+    let rotation = Quat::from_rotation_y(yaw);
+
+    // The crumb may have been dropped mid-bump, or the car is half buried, find the ground again
+    let rapier_context = read_rapier_context.single().ok()?;
+    let filter = QueryFilter::default().exclude_rigid_body(car).exclude_sensors();
+    let origin = Vec3::new(position.x, position.y + 50.0, position.z);
+    let ground = rapier_context
+        .cast_ray(origin, Vec3::NEG_Y, 200.0, true, filter)
+        .map(|(_entity, distance)| origin.y - distance)
+        .unwrap_or(position.y);
+
+    Some(Transform::from_translation(position.with_y(ground + LIFT_HEIGHT)).with_rotation(rotation))
+}
+
+/// The reset button: back on the wheels at the nearest safe spot, or where the car is for cars
+/// that don't leave a trail
+fn reset_on_request(
+    time: Res<Time>,
+    read_rapier_context: ReadRapierContext,
+    mut car_query: Query<(Entity, &mut DriveInput, &mut Transform, &mut Velocity, &mut StuckTimer, Option<&Recovery>), Without<PlayerOut>>,
+    mut car_recovered: MessageWriter<CarRecovered>,
+) {
+    for (car, mut drive_input, mut transform, mut velocity, mut stuck_timer, recovery) in car_query.iter_mut() {
+        if !drive_input.reset {
+            continue;
+        }
+        // Handled once, networked inputs would otherwise hold it until the next packet
+        drive_input.reset = false;
+
+        let (position, rotation) = recovery
+            .and_then(|recovery| closest_crumb(recovery, transform.translation, time.elapsed_secs()))
+            .map_or((transform.translation, transform.rotation), |crumb| (crumb.position, crumb.rotation));
+        let Some(pose) = grounded_pose(position, rotation, car, &read_rapier_context) else {
+            continue;
+        };
+
+        *transform = pose;
+        velocity.linvel = Vec3::ZERO;
+        velocity.angvel = Vec3::ZERO;
+        stuck_timer.reset();
+        car_recovered.write(CarRecovered { car });
+    }
+}
+
+/// Flipped and fallen cars go back to a breadcrumb and carry on, instead of their run ending,
+/// until they've used up their recoveries for the run
+pub fn recover_cars(
+    time: Res<Time>,
+    gameplay: Res<GameplaySettings>,
+    allowed: Res<RecoveryAllowed>,
+    read_rapier_context: ReadRapierContext,
+    mut car_out: MessageReader<CarOut>,
+    mut car_query: Query<(&mut Recovery, &mut Transform, &mut Velocity, &mut StuckTimer), Without<PlayerOut>>,
+    mut car_recovered: MessageWriter<CarRecovered>,
+) {
+    if !allowed.0 || !(gameplay.auto_recover || gameplay.respawn) {
+        return;
+    }
+
+    let now = time.elapsed_secs();
+    let mut recovered = Vec::new();
+    for out in car_out.read() {
+        // Running dry can't be driven out of
+        if !matches!(out.reason, RunEndReason::Flipped | RunEndReason::FellOff) {
+            continue;
+        }
+        // Flipping and falling in the same frame is still one recovery
+        if recovered.contains(&out.car) {
+            continue;
+        }
+        let Ok((mut recovery, mut transform, mut velocity, mut stuck_timer)) = car_query.get_mut(out.car) else {
+            continue;
+        };
+
+        // Out of recoveries, this one ends the run
+        if recovery.used >= RECOVERIES_PER_RUN {
+            continue;
+        }
+
+        // Auto-recover puts the car right where it went wrong, respawn at its latest crumb,
+        // which may be a little way back
+        let crumb = if gameplay.auto_recover {
+            closest_crumb(&recovery, transform.translation, now)
+        } else {
+            latest_crumb(&recovery, now)
+        };
+        // No trail yet, e.g. it went over on the start line, so the run ends after all
+        let Some(pose) = crumb.and_then(|crumb| grounded_pose(crumb.position, crumb.rotation, out.car, &read_rapier_context)) else {
+            continue;
+        };
+
+        *transform = pose;
+        velocity.linvel = Vec3::ZERO;
+        velocity.angvel = Vec3::ZERO;
+        recovery.used += 1;
+        if gameplay.auto_recover {
+            stuck_timer.reset();
+            bevy::log::info!("Car out ({:?}), recovered nearby, {} of {} this run", out.reason, recovery.used, RECOVERIES_PER_RUN);
+        } else {
+            stuck_timer.reset_with_grace(RESPAWN_GRACE);
+            bevy::log::info!("Car out ({:?}), respawned at its last safe spot, {} of {} this run", out.reason, recovery.used, RECOVERIES_PER_RUN);
+        }
+        recovered.push(out.car);
+        car_recovered.write(CarRecovered { car: out.car });
+    }
+}
+
+fn spawn_recovery_huds(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    camera_query: Query<(Entity, &PlayerCamera)>,
+    hud_query: Query<(&HudLine, &ChildOf), With<RecoveryHudText>>,
+) {
+    let node = Node {
+        width: Val::Percent(100.),
+        position_type: PositionType::Absolute,
+        justify_content: JustifyContent::Center,
+        top: Val::Px(30.),
+        ..default()
+    };
+    spawn_player_text_huds(&mut commands, &asset_server, &camera_query, &hud_query, node, Color::srgb(0.9, 0.9, 0.9), RecoveryHudText);
+}
+
+fn update_recovery_huds(
+    gameplay: Res<GameplaySettings>,
+    car_query: Query<(&PlayerCar, &Recovery)>,
+    mut text_query: Query<(&mut Text, &HudLine), With<RecoveryHudText>>,
+) {
+    for (mut text, hud) in text_query.iter_mut() {
+        let label = match car_query.iter().find(|(player, _)| player.0 == hud.player) {
+            Some((_, recovery)) if gameplay.auto_recover || gameplay.respawn => {
+                format!("recoveries left {}", RECOVERIES_PER_RUN - recovery.used.min(RECOVERIES_PER_RUN))
+            }
+            _ => String::new(),
+        };
+        if text.0 != label {
+            text.0 = label;
+        }
+    }
+}
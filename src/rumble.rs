@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use bevy::{
+    input::gamepad::{GamepadRumbleIntensity, GamepadRumbleRequest},
+    prelude::*,
+};
+use leafwing_input_manager::prelude::{ActionState, InputMap};
+
+use crate::{
+    car::PlayerCar,
+    impacts::{CarImpact, CarLanded},
+    input::CarAction,
+    settings::ControlSettings,
+    state::GameState,
+};
+
+pub struct RumblePlugin;
+
+impl Plugin for RumblePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RumbleState>()
+            .add_systems(
+                Update,
+                (rumble_on_landings_and_impacts, rumble_while_boosting).run_if(in_state(GameState::Running)),
+            )
+            .add_systems(OnEnter(GameState::GameOver), rumble_on_game_over);
+    }
+}
+
+// Impacts softer than this don't shake the controller, matches the crash sound
+const RUMBLE_IMPACT_FORCE: f32 = 150_000.0;
+// Boost rumble is sent as short back-to-back pulses so it stops as soon as boost does
+const BOOST_PULSE_SECONDS: f32 = 0.1;
+
+#[derive(Resource, Default)]
+struct RumbleState {
+    impact_cooldown: f32,
+    boost_pulse_timer: f32,
+}
+
+/// Gamepads driving a player's car, every connected one unless split screen bound it to one
+fn player_gamepads(gamepads: &Query<Entity, With<Gamepad>>, input_map: &InputMap<CarAction>) -> Vec<Entity> {
+    match input_map.gamepad() {
+        Some(gamepad) => gamepads.iter().filter(|entity| *entity == gamepad).collect(),
+        None => gamepads.iter().collect(),
+    }
+}
+
+/// Rumble the given gamepads, scaled by the rumble setting
+fn rumble(
+    rumble_requests: &mut MessageWriter<GamepadRumbleRequest>,
+    gamepads: impl IntoIterator<Item = Entity>,
+    settings: &ControlSettings,
+    strong: f32,
+    weak: f32,
+    seconds: f32,
+) {
+    if settings.rumble <= 0.0 {
+        return;
+    }
+
+    let intensity = GamepadRumbleIntensity {
+        strong_motor: (strong * settings.rumble).clamp(0.0, 1.0),
+        weak_motor: (weak * settings.rumble).clamp(0.0, 1.0),
+    };
+    for gamepad in gamepads {
+        rumble_requests.write(GamepadRumbleRequest::Add {
+            gamepad,
+            intensity,
+            duration: Duration::from_secs_f32(seconds),
+        });
+    }
+}
+
+fn rumble_on_landings_and_impacts(
+    time: Res<Time>,
+    settings: Res<ControlSettings>,
+    mut rumble_state: ResMut<RumbleState>,
+    mut car_landed: MessageReader<CarLanded>,
+    mut car_impact: MessageReader<CarImpact>,
+    mut rumble_requests: MessageWriter<GamepadRumbleRequest>,
+    gamepads: Query<Entity, With<Gamepad>>,
+    player_query: Query<&InputMap<CarAction>, With<PlayerCar>>,
+) {
+    rumble_state.impact_cooldown = (rumble_state.impact_cooldown - time.delta_secs()).max(0.0);
+
+    // AI cars crash and land too, only a player's car shakes their controller
+    for landed in car_landed.read() {
+        let Ok(input_map) = player_query.get(landed.car) else {
+            continue;
+        };
+        // Same scale as the landing thud, harder landings shake longer too
+        let strength = (landed.impact_speed / 60.0).clamp(0.2, 1.0);
+        let targets = player_gamepads(&gamepads, input_map);
+        rumble(&mut rumble_requests, targets, &settings, strength, strength * 0.5, 0.1 + strength * 0.25);
+    }
+
+    for impact in car_impact.read() {
+        let Ok(input_map) = player_query.get(impact.car) else {
+            continue;
+        };
+        // Contact forces are reported every step while touching, one pulse per hit
+        if impact.force < RUMBLE_IMPACT_FORCE || rumble_state.impact_cooldown > 0.0 {
+            continue;
+        }
+        rumble_state.impact_cooldown = 0.4;
+        let strength = (impact.force / (RUMBLE_IMPACT_FORCE * 4.0)).clamp(0.3, 1.0);
+        rumble(&mut rumble_requests, player_gamepads(&gamepads, input_map), &settings, strength, strength, 0.12);
+    }
+}
+
+fn rumble_while_boosting(
+    time: Res<Time>,
+    settings: Res<ControlSettings>,
+    mut rumble_state: ResMut<RumbleState>,
+    mut rumble_requests: MessageWriter<GamepadRumbleRequest>,
+    gamepads: Query<Entity, With<Gamepad>>,
+    car_query: Query<(&ActionState<CarAction>, &InputMap<CarAction>), With<PlayerCar>>,
+) {
+    rumble_state.boost_pulse_timer -= time.delta_secs();
+    if rumble_state.boost_pulse_timer > 0.0 {
+        return;
+    }
+
+    let mut targets = Vec::new();
+    for (action_state, input_map) in car_query.iter() {
+        if action_state.pressed(&CarAction::Boost) {
+            targets.extend(player_gamepads(&gamepads, input_map));
+        }
+    }
+    if !targets.is_empty() {
+        rumble_state.boost_pulse_timer = BOOST_PULSE_SECONDS;
+        // Just the weak motor, a hum rather than a shake
+        rumble(&mut rumble_requests, targets, &settings, 0.0, 0.25, BOOST_PULSE_SECONDS);
+    }
+}
+
+fn rumble_on_game_over(
+    settings: Res<ControlSettings>,
+    mut rumble_requests: MessageWriter<GamepadRumbleRequest>,
+    gamepads: Query<Entity, With<Gamepad>>,
+) {
+    // Clear out any boost or landing rumble first so the pulse stands out
+    for gamepad in gamepads.iter() {
+        rumble_requests.write(GamepadRumbleRequest::Stop { gamepad });
+    }
+    rumble(&mut rumble_requests, gamepads.iter(), &settings, 1.0, 1.0, 0.6);
+}
@@ -0,0 +1,151 @@
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::{CollidingEntities, Sensor, Velocity};
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::{car::{CarRecovered, PlayerOut}, impacts::on_ground, input::CarAction, state::GameState};
+
+pub struct RunPlugin;
+
+impl Plugin for RunPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<RunEnded>()
+            .init_resource::<LastRun>()
+            .add_systems(OnEnter(GameState::Running), reset_run_stats)
+            .add_systems(Update, track_run_stats.run_if(in_state(GameState::Running)));
+    }
+}
+
+/// Why a run ended - shown on the results screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunEndReason {
+    Flipped,
+    FellOff,
+    OutOfFuel,
+}
+
+impl RunEndReason {
+    pub fn title(&self) -> &'static str {
+        match self {
+            Self::Flipped => "FLIPPED OVER",
+            Self::FellOff => "FELL OFF THE WORLD",
+            Self::OutOfFuel => "OUT OF FUEL",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Flipped => "the car ended up on its side",
+            Self::FellOff => "the car drove off the edge of the desert",
+            Self::OutOfFuel => "the tank ran dry and the car rolled to a stop",
+        }
+    }
+}
+
+/// Sent once when a player's run ends, carrying the reason and the stats of the run
+#[derive(Message, Debug, Clone)]
+pub struct RunEnded {
+    pub player: usize,
+    pub reason: RunEndReason,
+    pub duration: f32,
+    pub distance: f32,
+    pub max_speed: f32,
+    pub average_speed: f32,
+    pub air_time: f32,
+    pub boost_time: f32,
+    pub pickups: u32,
+}
+
+/// Stats accumulated on each player's car while the game is in the Running state
+#[derive(Component, Debug, Default, Clone)]
+pub struct RunStats {
+    pub duration: f32,
+    pub distance: f32,
+    pub max_speed: f32,
+    pub air_time: f32,
+    pub boost_time: f32,
+    pub pickups: u32,
+    last_position: Option<Vec3>,
+}
+
+impl RunStats {
+    pub fn finish(&self, player: usize, reason: RunEndReason) -> RunEnded {
+        let average_speed = if self.duration > 0.0 {
+            self.distance / self.duration
+        } else {
+            0.0
+        };
+
+        RunEnded {
+            player,
+            reason,
+            duration: self.duration,
+            distance: self.distance,
+            max_speed: self.max_speed,
+            average_speed,
+            air_time: self.air_time,
+            boost_time: self.boost_time,
+            pickups: self.pickups,
+        }
+    }
+}
+
+/// The most recent finished runs, one per player in the order they ended, read by the game over screen
+#[derive(Resource, Debug, Default)]
+pub struct LastRun(pub Vec<RunEnded>);
+
+fn reset_run_stats(mut stats_query: Query<&mut RunStats>, mut last_run: ResMut<LastRun>) {
+    for mut stats in stats_query.iter_mut() {
+        *stats = RunStats::default();
+    }
+    last_run.0.clear();
+}
+
+fn track_run_stats(
+    time: Res<Time>,
+    mut car_recovered: MessageReader<CarRecovered>,
+    mut car_query: Query<(&mut RunStats, &Transform, &Velocity, &CollidingEntities, &ActionState<CarAction>), Without<PlayerOut>>,
+    sensor_query: Query<(), With<Sensor>>,
+) {
+    // A car put back on its wheels jumped there, don't count the jump as distance
+    for recovered in car_recovered.read() {
+        if let Ok((mut stats, ..)) = car_query.get_mut(recovered.car) {
+            stats.last_position = None;
+        }
+    }
+
+    // Each player's clock stops when their own run ends
+    for (mut stats, transform, velocity, colliding_entities, action_state) in car_query.iter_mut() {
+        let dt = time.delta_secs();
+        stats.duration += dt;
+
+        // Only count horizontal distance so falling off the map doesn't pad the numbers
+        let position = transform.translation;
+        if let Some(last_position) = stats.last_position {
+            let step = (position - last_position).with_y(0.0).length();
+            stats.distance += step;
+        }
+        stats.last_position = Some(position);
+
+        stats.max_speed = stats.max_speed.max(velocity.linvel.length());
+
+        if !on_ground(colliding_entities, &sensor_query) {
+            stats.air_time += dt;
+        }
+        if action_state.pressed(&CarAction::Boost) {
+            stats.boost_time += dt;
+        }
+    }
+}
+
+pub fn record_run_ended(mut run_ended: MessageReader<RunEnded>, mut last_run: ResMut<LastRun>) {
+    for ended in run_ended.read() {
+        bevy::log::info!("Run ended: {:?}", ended);
+        last_run.0.push(ended.clone());
+    }
+}
+
+pub fn format_duration(seconds: f32) -> String {
+    let minutes = (seconds / 60.0).floor() as u32;
+    let seconds = seconds - minutes as f32 * 60.0;
+    format!("{}:{:04.1}", minutes, seconds)
+}
@@ -0,0 +1,386 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use bevy::prelude::*;
+use leafwing_input_manager::prelude::InputMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    graphics::GraphicsSettings,
+    input::CarAction,
+    run::RunEnded,
+    settings::{AudioSettings, ControlSettings, GameplaySettings},
+    storage,
+};
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        // Settings are their own resources so systems don't need the whole save to read them
+        let save_data = SaveData::load();
+        app.insert_resource(save_data.audio.clone())
+            .insert_resource(save_data.graphics.clone())
+            .insert_resource(save_data.controls.clone())
+            .insert_resource(save_data.gameplay.clone())
+            .insert_resource(save_data)
+            .add_systems(
+                Update,
+                (
+                    sync_settings,
+                    record_high_scores,
+                    write_save.after(sync_settings).after(record_high_scores),
+                ),
+            );
+    }
+}
+
+const SAVE_KEY: &str = "save";
+// An unreadable save is kept here instead of being overwritten, so it can be recovered by hand
+const CORRUPT_SAVE_KEY: &str = "save.corrupt";
+// Before the save file each settings resource was stored under its own key
+const LEGACY_AUDIO_SETTINGS_KEY: &str = "audio_settings";
+const LEGACY_GRAPHICS_SETTINGS_KEY: &str = "graphics_settings";
+
+/// Bump this when SaveData changes shape, and add a step to `migrate`
+pub const SAVE_VERSION: u32 = 2;
+
+/// Everything kept between sessions
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SaveData {
+    pub version: u32,
+    pub audio: AudioSettings,
+    pub graphics: GraphicsSettings,
+    pub controls: ControlSettings,
+    pub gameplay: GameplaySettings,
+    /// Custom controls for the first player, None uses the defaults
+    pub key_bindings: Option<InputMap<CarAction>>,
+    /// Best lap time in seconds, by map name
+    pub best_lap_times: BTreeMap<String, f32>,
+    pub high_scores: HighScores,
+    pub unlocks: Unlocks,
+    /// Ids of every pickup ever collected, by map name, for completion
+    pub collected_pickups: BTreeMap<String, BTreeSet<u32>>,
+}
+
+impl Default for SaveData {
+    fn default() -> Self {
+        Self {
+            version: SAVE_VERSION,
+            audio: AudioSettings::default(),
+            graphics: GraphicsSettings::default(),
+            controls: ControlSettings::default(),
+            gameplay: GameplaySettings::default(),
+            key_bindings: None,
+            best_lap_times: BTreeMap::new(),
+            high_scores: HighScores::default(),
+            unlocks: Unlocks::default(),
+            collected_pickups: BTreeMap::new(),
+        }
+    }
+}
+
+/// Bests across every run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HighScores {
+    pub longest_run: f32,
+    pub farthest_distance: f32,
+    pub top_speed: f32,
+    pub most_air_time: f32,
+    /// Farthest distance in survival mode, where distance is the score
+    pub survival_distance: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Unlocks {
+    pub cars: BTreeSet<String>,
+    pub maps: BTreeSet<String>,
+}
+
+impl Default for Unlocks {
+    fn default() -> Self {
+        Self {
+            cars: BTreeSet::from(["desertcar".to_string()]),
+            maps: BTreeSet::from(["desert".to_string()]),
+        }
+    }
+}
+
+impl HighScores {
+    fn clamp(&mut self) {
+        for best in [
+            &mut self.longest_run,
+            &mut self.farthest_distance,
+            &mut self.top_speed,
+            &mut self.most_air_time,
+            &mut self.survival_distance,
+        ] {
+            *best = best.max(0.0);
+        }
+    }
+}
+
+impl SaveData {
+    fn load() -> Self {
+        let (save_data, unreadable) = Self::load_from(storage::load(SAVE_KEY), legacy_save);
+        if let Some(raw) = unreadable
+            && let Err(error) = storage::save(CORRUPT_SAVE_KEY, &raw)
+        {
+            bevy::log::warn!("Failed to back up save data: {}", error);
+        }
+        save_data
+    }
+
+    /// The save to start from, and the stored save to keep aside if it couldn't be read
+    fn load_from(raw: Option<String>, legacy_save: impl FnOnce() -> Option<Value>) -> (Self, Option<String>) {
+        let value = match &raw {
+            Some(raw) => serde_json::from_str(raw).map_err(|error| error.to_string()),
+            None => match legacy_save() {
+                Some(value) => {
+                    bevy::log::info!("Moving settings into the save file");
+                    Ok(value)
+                }
+                None => return (Self::default(), None),
+            },
+        };
+
+        match value.and_then(Self::from_value) {
+            Ok(save_data) => (save_data, None),
+            Err(error) => {
+                bevy::log::warn!("Save data unreadable, starting fresh: {}", error);
+                (Self::default(), raw)
+            }
+        }
+    }
+
+    /// Bring saved JSON from any older version up to date
+    fn from_value(mut value: Value) -> Result<Self, String> {
+        if !value.is_object() {
+            return Err("save is not a JSON object".to_string());
+        }
+        let version = value.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+        if version > SAVE_VERSION {
+            return Err(format!("save is from a newer version ({version})"));
+        }
+
+        for from in version..SAVE_VERSION {
+            value = migrate(from, value)?;
+        }
+        let mut save_data: Self = serde_json::from_value(value).map_err(|error| error.to_string())?;
+        save_data.clamp();
+        Ok(save_data)
+    }
+
+    /// Pull hand-edited or damaged values back into the range the settings menu allows
+    fn clamp(&mut self) {
+        self.audio.clamp();
+        self.graphics.clamp();
+        self.controls.clamp();
+        self.high_scores.clamp();
+        // A lap can't take no time at all
+        self.best_lap_times.retain(|_, time| time.is_finite() && *time > 0.0);
+    }
+}
+
+/// Migrate save JSON from one version to the next
+fn migrate(from: u32, mut value: Value) -> Result<Value, String> {
+    match from {
+        // Version 0 is the separate settings keys gathered up by legacy_save,
+        // already in the same shape as version 1
+        0 => {
+            value["version"] = Value::from(1);
+            Ok(value)
+        }
+        // Version 1 saves written while key bindings and lap times were briefly left out don't
+        // have them, start those empty and keep them where they are there
+        1 => {
+            if value.get("key_bindings").is_none() {
+                value["key_bindings"] = Value::Null;
+            }
+            if value.get("best_lap_times").is_none() {
+                value["best_lap_times"] = serde_json::json!({});
+            }
+            value["version"] = Value::from(2);
+            Ok(value)
+        }
+        _ => Err(format!("no migration from version {from}")),
+    }
+}
+
+/// Gather settings saved before the save file existed, as a version 0 save
+fn legacy_save() -> Option<Value> {
+    let mut value = serde_json::json!({ "version": 0 });
+    let mut found = false;
+
+    for (key, field) in [(LEGACY_AUDIO_SETTINGS_KEY, "audio"), (LEGACY_GRAPHICS_SETTINGS_KEY, "graphics")] {
+        // A bad legacy entry just falls back to defaults, the rest still migrates
+        if let Some(settings) = storage::load(key).and_then(|raw| serde_json::from_str::<Value>(&raw).ok()) {
+            value[field] = settings;
+            found = true;
+        }
+    }
+
+    found.then_some(value)
+}
+
+fn sync_settings(
+    audio: Res<AudioSettings>,
+    graphics: Res<GraphicsSettings>,
+    controls: Res<ControlSettings>,
+    gameplay: Res<GameplaySettings>,
+    mut save_data: ResMut<SaveData>,
+) {
+    if audio.is_changed() && !audio.is_added() {
+        save_data.audio = audio.clone();
+    }
+    if graphics.is_changed() && !graphics.is_added() {
+        save_data.graphics = graphics.clone();
+    }
+    if controls.is_changed() && !controls.is_added() {
+        save_data.controls = controls.clone();
+    }
+    if gameplay.is_changed() && !gameplay.is_added() {
+        save_data.gameplay = gameplay.clone();
+    }
+}
+
+fn record_high_scores(
+    mut run_ended: MessageReader<RunEnded>,
+    gameplay: Res<GameplaySettings>,
+    mut save_data: ResMut<SaveData>,
+) {
+    for run in run_ended.read() {
+        let best = &save_data.high_scores;
+        let survival_best = gameplay.survival && run.distance > best.survival_distance;
+        // Only touch the save when something improved so it isn't rewritten for nothing
+        if !survival_best
+            && run.duration <= best.longest_run
+            && run.distance <= best.farthest_distance
+            && run.max_speed <= best.top_speed
+            && run.air_time <= best.most_air_time
+        {
+            continue;
+        }
+
+        let best = &mut save_data.high_scores;
+        best.longest_run = best.longest_run.max(run.duration);
+        best.farthest_distance = best.farthest_distance.max(run.distance);
+        best.top_speed = best.top_speed.max(run.max_speed);
+        best.most_air_time = best.most_air_time.max(run.air_time);
+        if survival_best {
+            best.survival_distance = run.distance;
+        }
+    }
+}
+
+fn write_save(save_data: Res<SaveData>) {
+    // Skip the frame the save was loaded, there's nothing new to write
+    if save_data.is_changed() && !save_data.is_added() {
+        storage::save_json(SAVE_KEY, &*save_data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn legacy_settings_migrate_to_the_current_version() {
+        let legacy = json!({
+            "version": 0,
+            "audio": { "master": 0.5, "music_enabled": false },
+            "graphics": { "shadows": false },
+        });
+        let (save_data, unreadable) = SaveData::load_from(None, || Some(legacy));
+
+        assert!(unreadable.is_none());
+        assert_eq!(save_data.version, SAVE_VERSION);
+        assert_eq!(save_data.audio.master, 0.5);
+        assert!(!save_data.audio.music_enabled);
+        assert!(!save_data.graphics.shadows);
+        // Everything the legacy keys didn't cover starts from defaults
+        assert_eq!(save_data.controls, ControlSettings::default());
+        assert!(save_data.key_bindings.is_none());
+        assert!(save_data.best_lap_times.is_empty());
+    }
+
+    #[test]
+    fn version_one_gains_key_bindings_and_lap_times() {
+        let save_data = SaveData::from_value(json!({ "version": 1, "high_scores": { "top_speed": 42.0 } })).unwrap();
+        assert_eq!(save_data.version, 2);
+        assert_eq!(save_data.high_scores.top_speed, 42.0);
+        assert!(save_data.key_bindings.is_none());
+        assert!(save_data.best_lap_times.is_empty());
+
+        // Version 1 saves from before they were left out still have their lap times
+        let save_data = SaveData::from_value(json!({ "version": 1, "best_lap_times": { "desert": 61.5 } })).unwrap();
+        assert_eq!(save_data.best_lap_times.get("desert"), Some(&61.5));
+    }
+
+    #[test]
+    fn no_save_starts_from_defaults() {
+        let (save_data, unreadable) = SaveData::load_from(None, || None);
+        assert!(unreadable.is_none());
+        assert_eq!(save_data.version, SAVE_VERSION);
+        assert_eq!(save_data.audio, AudioSettings::default());
+    }
+
+    #[test]
+    fn corrupt_save_starts_fresh_and_is_kept_aside() {
+        for raw in [
+            "{\"version\": 2, \"audio\": {\"master\": 0.",
+            "not json at all",
+            "[1, 2, 3]",
+            "{\"version\": 2, \"audio\": {\"master\": \"loud\"}}",
+        ] {
+            let (save_data, unreadable) = SaveData::load_from(Some(raw.to_string()), || panic!("legacy keys read over a save"));
+            assert_eq!(unreadable.as_deref(), Some(raw));
+            assert_eq!(save_data.audio, AudioSettings::default());
+        }
+    }
+
+    #[test]
+    fn newer_save_is_kept_aside() {
+        let raw = json!({ "version": SAVE_VERSION + 1 }).to_string();
+        let (save_data, unreadable) = SaveData::load_from(Some(raw.clone()), || None);
+        assert_eq!(unreadable, Some(raw));
+        assert_eq!(save_data.version, SAVE_VERSION);
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        let save_data = SaveData::from_value(json!({
+            "version": SAVE_VERSION,
+            "audio": { "master": 3.0, "sfx": -1.0 },
+            "high_scores": { "longest_run": -5.0 },
+            "best_lap_times": { "desert": 0.0, "dunes": 75.0 },
+        }))
+        .unwrap();
+        assert_eq!(save_data.audio.master, 1.0);
+        assert_eq!(save_data.audio.sfx, 0.0);
+        assert_eq!(save_data.high_scores.longest_run, 0.0);
+        assert_eq!(save_data.best_lap_times.len(), 1);
+    }
+
+    #[test]
+    fn saved_data_loads_back() {
+        let mut save_data = SaveData::default();
+        save_data.audio.music = 0.3;
+        save_data.gameplay.survival = true;
+        save_data.best_lap_times.insert("desert".to_string(), 58.25);
+        save_data.collected_pickups.entry("desert".to_string()).or_default().insert(7);
+
+        let raw = serde_json::to_string(&save_data).unwrap();
+        let (loaded, unreadable) = SaveData::load_from(Some(raw), || None);
+        assert!(unreadable.is_none());
+        assert_eq!(loaded.audio.music, 0.3);
+        assert!(loaded.gameplay.survival);
+        assert_eq!(loaded.best_lap_times.get("desert"), Some(&58.25));
+        assert!(loaded.collected_pickups["desert"].contains(&7));
+    }
+}
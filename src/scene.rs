@@ -1,15 +1,16 @@
 use bevy::{
     asset::LoadState,
+    ecs::system::EntityCommands,
     gltf::{Gltf, GltfMesh},
     prelude::*,
     mesh::{Indices, VertexAttributeValues},
 };
 use bevy_rapier3d::{
     math::Vect,
-    prelude::{ActiveEvents, AdditionalMassProperties, Collider, CollidingEntities, ExternalForce, GravityScale, RigidBody, Velocity},
+    prelude::{ActiveEvents, AdditionalMassProperties, Collider, CollidingEntities, ContactForceEventThreshold, ExternalForce, GravityScale, RigidBody, Velocity},
 };
 
-use crate::{assets::SceneResource, car::{Car, StuckTimer, CAR_START_POSITION}, input, movement::CarMovements};
+use crate::{ai::{AiDriver, AiSettings}, assets::SceneResource, car::{player_start, Car, PlayerCar, SpawnPoint, StuckTimer, CAR_START_POSITION}, car_audio::EngineSound, damage::Damage, fuel::Fuel, headlights::Headlights, impacts::Airborne, input::{self, DriveInput, Throttle}, movement::{CarMovements, Drift}, recover::Recovery, run::RunStats, save::SaveData, split_screen::SplitScreen, surface::{GroundSurface, Terrain, TerrainSurfaces}, tracks::WheelEffects};
 
 pub fn setup(
     asset_server: Res<AssetServer>,
@@ -19,6 +20,9 @@ pub fn setup(
     scene_assets: ResMut<SceneResource>,
     mut commands: Commands,
     car_query: Query<(), With<Car>>,
+    save_data: Res<SaveData>,
+    ai_settings: Res<AiSettings>,
+    split_screen: Res<SplitScreen>,
 ) {
     // Prevent duplicate spawning when re-entering Running state (e.g., from GameOver -> Running)
     if !car_query.is_empty() {
@@ -30,31 +34,28 @@ pub fn setup(
     if let LoadState::Loaded = asset_server.load_state(&scene_assets.handle)
         && let Some(scenes_gltf) = assets_gltf.get(&scene_assets.handle) {
             bevy::log::info!("Assets loaded, spawning car and terrain");
-            let initial_transform = Transform {
-                translation: CAR_START_POSITION,
-                rotation: Quat::IDENTITY, // Ensure car starts with correct orientation
-                scale: Vec3::ONE,
-            };
+            let car_scene = scenes_gltf.named_scenes["CAR"].clone();
+            for player in 0..split_screen.players {
+                let initial_transform = Transform {
+                    translation: player_start(player),
+                    rotation: Quat::IDENTITY, // Ensure car starts with correct orientation
+                    scale: Vec3::ONE,
+                };
+                // The first player keeps the keyboard and any custom bindings, the others get a gamepad each
+                let input_map = if player == 0 {
+                    input::player_input_map(save_data.key_bindings.as_ref())
+                } else {
+                    input::gamepad_input_map()
+                };
+                spawn_car(&mut commands, car_scene.clone(), initial_transform)
+                    .insert((PlayerCar(player), input_map, Throttle::default(), DriveInput::default(), Fuel::default(), Recovery::default(), RunStats::default()));
+            }
 
-            commands
-                .spawn((
-                    SceneRoot(scenes_gltf.named_scenes["CAR"].clone()),
-                    initial_transform,
-                    RigidBody::Dynamic,
-                    Collider::round_cuboid(3.8, 1.4, 2.2, 0.3),
-                        CollidingEntities::default(), // Track collisions to detect ground contact
-                        AdditionalMassProperties::Mass(50.0), // Make car heavier but still movable
-                        Velocity::zero(),
-                        ExternalForce::default(),
-                        GravityScale(20.0), // Very high for fast falling 
-                        CarMovements::default(),
-                        StuckTimer::default(), // Track if car is stuck
-                        input::default_input_map(),
-                        Car {
-                        thrust: Vec3::new(5000.0, 2500.0, 2500.0), // Increased thrust to move heavier car
-                        drag: Vec3::new(100.0, 2.0, 100.0), // Minimal Y drag for very fast falling
-                    },
-                ));
+            // AI cars line up around the player and drive themselves
+            for (difficulty, position) in ai_settings.grid(CAR_START_POSITION) {
+                spawn_car(&mut commands, car_scene.clone(), Transform::from_translation(position))
+                    .insert(AiDriver::new(difficulty));
+            }
 
             // Spawn terrain scene - it will spawn even if we can't extract mesh data for collider
             let terrain_scene_handle = scenes_gltf.named_scenes.get("DESERT");
@@ -63,7 +64,7 @@ pub fn setup(
                 let mut desert_entity = commands.spawn(SceneRoot(terrain_scene_handle.clone()));
                 
                 // Terrain needs to be a static rigid body for collisions to work
-                desert_entity.insert(RigidBody::Fixed);
+                desert_entity.insert((RigidBody::Fixed, Terrain));
                 
                 bevy::log::info!("Desert terrain scene spawned");
                 
@@ -74,12 +75,25 @@ pub fn setup(
                 if let Some(desert_mesh_handle) = desert_mesh_handle {
                     bevy::log::info!("Found DESERT mesh handle (solid terrain for collision)");
                     
-                    let desert_mesh: Option<&Mesh> = gltf_meshes
+                    let desert_primitive = gltf_meshes
                         .get(desert_mesh_handle)
                         .and_then(|gltf_mesh| {
                             bevy::log::info!("GltfMesh has {} primitives", gltf_mesh.primitives.len());
                             gltf_mesh.primitives.first()
-                        })
+                        });
+
+                    // Surface types can be authored through the terrain material's name
+                    let desert_material_name = desert_primitive
+                        .and_then(|desert_primitive| desert_primitive.material.as_ref())
+                        .and_then(|material| {
+                            scenes_gltf
+                                .named_materials
+                                .iter()
+                                .find(|(_, handle)| *handle == material)
+                                .map(|(name, _)| name.to_string())
+                        });
+
+                    let desert_mesh: Option<&Mesh> = desert_primitive
                         .and_then(|desert_primitive| {
                             bevy::log::info!("Trying to get mesh from primitive: {:?}", desert_primitive.mesh);
                             meshes.get(&desert_primitive.mesh)
@@ -108,6 +122,12 @@ pub fn setup(
 
                                 let vertex_count = vertices.len();
                                 let triangle_count = indices.len();
+                                desert_entity.commands().insert_resource(TerrainSurfaces::classify(
+                                    desert_mesh,
+                                    &vertices,
+                                    &indices,
+                                    desert_material_name.as_deref(),
+                                ));
                                 if let Ok(collider) = Collider::trimesh(vertices, indices) {
                                     desert_entity.insert(collider);
                                     desert_entity.insert(ActiveEvents::COLLISION_EVENTS);
@@ -133,6 +153,12 @@ pub fn setup(
 
                                 let vertex_count = vertices.len();
                                 let triangle_count = indices.len();
+                                desert_entity.commands().insert_resource(TerrainSurfaces::classify(
+                                    desert_mesh,
+                                    &vertices,
+                                    &indices,
+                                    desert_material_name.as_deref(),
+                                ));
                                 if let Ok(collider) = Collider::trimesh(vertices, indices) {
                                     desert_entity.insert(collider);
                                     desert_entity.insert(ActiveEvents::COLLISION_EVENTS);
@@ -166,3 +192,44 @@ pub fn setup(
             }
     }
 }
+
+/// Spawn a car with its physics and effects, ready for player input or an AI driver to be added
+pub fn spawn_car<'a>(commands: &'a mut Commands, car_scene: Handle<Scene>, transform: Transform) -> EntityCommands<'a> {
+    commands.spawn((
+        SceneRoot(car_scene),
+        transform,
+        SpawnPoint(transform),
+        // Physics
+        (
+            RigidBody::Dynamic,
+            Collider::round_cuboid(3.8, 1.4, 2.2, 0.3),
+            CollidingEntities::default(), // Track collisions to detect ground contact
+            // Report hard hits for impact sounds, resting on the ground stays under the threshold
+            ActiveEvents::COLLISION_EVENTS | ActiveEvents::CONTACT_FORCE_EVENTS,
+            ContactForceEventThreshold(100_000.0),
+            AdditionalMassProperties::Mass(50.0), // Make car heavier but still movable
+            Velocity::zero(),
+            ExternalForce::default(),
+            GravityScale(20.0), // Very high for fast falling 
+        ),
+        // Driving state and effects
+        (
+            CarMovements::default(),
+            Drift::default(),
+            GroundSurface::default(),
+            WheelEffects::default(),
+            Headlights::default(),
+            Airborne::default(),
+            EngineSound::default(),
+            StuckTimer::default(), // Track if car is stuck
+            Damage::default(),
+        ),
+        Car {
+            thrust: Vec3::new(5000.0, 2500.0, 2500.0), // Increased thrust to move heavier car
+            drag: Vec3::new(100.0, 2.0, 100.0), // Minimal Y drag for very fast falling
+            peak_slip_angle: 12.0f32.to_radians(),
+            sliding_grip: 0.35,
+            handbrake_grip: 0.1,
+        },
+    ))
+}
@@ -0,0 +1,600 @@
+use bevy::{prelude::*, window::WindowFocused};
+use bevy_kira_audio::{AudioApp, AudioChannel, AudioControl};
+use leafwing_input_manager::prelude::ActionState;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    graphics::{GraphicsPreset, GraphicsSettings, MAX_FOV, MIN_FOV},
+    input::CarAction,
+    synth,
+};
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_audio_channel::<MusicChannel>()
+            .add_audio_channel::<SfxChannel>()
+            .init_resource::<WindowFocus>()
+            .add_systems(
+                Update,
+                (
+                    toggle_settings_panel,
+                    handle_settings_buttons,
+                    track_window_focus,
+                    apply_audio_settings.after(handle_settings_buttons).after(track_window_focus),
+                    update_settings_text.after(handle_settings_buttons),
+                ),
+            );
+    }
+}
+
+const VOLUME_STEP: f32 = 0.1;
+const RENDER_SCALE_STEP: f32 = 0.25;
+const FOV_STEP: f32 = 5.0;
+const DEADZONE_STEP: f32 = 0.05;
+const MAX_DEADZONE: f32 = 0.5;
+const RAMP_STEP: f32 = 0.1;
+const MAX_RAMP: f32 = 1.0;
+
+/// Music plays on its own channel so it can be turned down apart from the effects
+#[derive(Resource)]
+pub struct MusicChannel;
+
+/// Engine, impacts, wind and the rest of the sound effects
+#[derive(Resource)]
+pub struct SfxChannel;
+
+/// Audio preferences, kept in the save data
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioSettings {
+    pub master: f32,
+    pub music: f32,
+    pub sfx: f32,
+    pub music_enabled: bool,
+    /// Silence everything while the window is in the background
+    pub mute_on_focus_loss: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master: 1.0,
+            music: 0.8,
+            sfx: 1.0,
+            music_enabled: true,
+            mute_on_focus_loss: true,
+        }
+    }
+}
+
+impl AudioSettings {
+    pub fn music_volume(&self) -> f32 {
+        if self.music_enabled { self.master * self.music } else { 0.0 }
+    }
+
+    pub fn sfx_volume(&self) -> f32 {
+        self.master * self.sfx
+    }
+
+    /// Keep loaded volumes within what the sliders can reach
+    pub fn clamp(&mut self) {
+        for volume in [&mut self.master, &mut self.music, &mut self.sfx] {
+            *volume = volume.clamp(0.0, 1.0);
+        }
+    }
+
+    fn volume_mut(&mut self, slider: VolumeSlider) -> &mut f32 {
+        match slider {
+            VolumeSlider::Master => &mut self.master,
+            VolumeSlider::Music => &mut self.music,
+            VolumeSlider::Sfx => &mut self.sfx,
+        }
+    }
+}
+
+/// Controller preferences, kept in the save data
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlSettings {
+    /// Gamepad rumble strength, 0 turns it off
+    pub rumble: f32,
+    /// Trigger travel ignored before the throttle starts to respond
+    pub trigger_deadzone: f32,
+    /// Seconds for keyboard throttle to build to full, 0 is instant
+    pub keyboard_ramp: f32,
+}
+
+impl Default for ControlSettings {
+    fn default() -> Self {
+        Self {
+            rumble: 1.0,
+            trigger_deadzone: 0.1,
+            keyboard_ramp: 0.0,
+        }
+    }
+}
+
+impl ControlSettings {
+    /// Keep loaded values within what the settings menu can reach
+    pub fn clamp(&mut self) {
+        self.rumble = self.rumble.clamp(0.0, 1.0);
+        self.trigger_deadzone = self.trigger_deadzone.clamp(0.0, MAX_DEADZONE);
+        self.keyboard_ramp = self.keyboard_ramp.clamp(0.0, MAX_RAMP);
+    }
+}
+
+/// How the game plays, kept in the save data
+#[derive(Resource, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameplaySettings {
+    /// Fuel drains as you drive and running dry ends the run
+    pub survival: bool,
+    /// Put a flipped car back on its wheels nearby a few times a run, instead of ending the run
+    pub auto_recover: bool,
+    /// Like auto_recover, but back at the latest safe spot on the trail with a moment to settle
+    pub respawn: bool,
+}
+
+#[derive(Resource)]
+struct WindowFocus(bool);
+
+impl Default for WindowFocus {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VolumeSlider {
+    Master,
+    Music,
+    Sfx,
+}
+
+#[derive(Component)]
+struct SettingsPanel;
+
+#[derive(Component, Clone, Copy)]
+enum SettingsButton {
+    Decrease(VolumeSlider),
+    Increase(VolumeSlider),
+    ToggleMusic,
+    ToggleMuteOnFocusLoss,
+    CyclePreset,
+    ToggleShadows,
+    CycleMsaa,
+    DecreaseRenderScale,
+    IncreaseRenderScale,
+    ToggleWireframe,
+    ToggleVsync,
+    DecreaseFov,
+    IncreaseFov,
+    DecreaseRumble,
+    IncreaseRumble,
+    DecreaseDeadzone,
+    IncreaseDeadzone,
+    DecreaseRamp,
+    IncreaseRamp,
+    ToggleSurvival,
+    ToggleAutoRecover,
+    ToggleRespawn,
+    Close,
+}
+
+/// Text showing the current value of a setting
+#[derive(Component, Clone, Copy)]
+enum SettingsValue {
+    Volume(VolumeSlider),
+    MusicEnabled,
+    MuteOnFocusLoss,
+    Preset,
+    Shadows,
+    Msaa,
+    RenderScale,
+    Wireframe,
+    Vsync,
+    Fov,
+    Rumble,
+    Deadzone,
+    Ramp,
+    Survival,
+    AutoRecover,
+    Respawn,
+}
+
+fn toggle_settings_panel(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    panel_query: Query<Entity, With<SettingsPanel>>,
+    action_state_query: Query<&ActionState<CarAction>>,
+) {
+    if !action_state_query
+        .iter()
+        .any(|action_state| action_state.just_pressed(&CarAction::Settings))
+    {
+        return;
+    }
+
+    if let Ok(panel) = panel_query.single() {
+        commands.entity(panel).despawn();
+    } else {
+        spawn_settings_panel(&mut commands, &asset_server);
+    }
+}
+
+fn spawn_settings_panel(commands: &mut Commands, asset_server: &AssetServer) {
+    let font_handle = asset_server.load("font/NotoSansMono-Bold.ttf");
+
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            GlobalZIndex(10),
+            SettingsPanel,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        row_gap: Val::Px(8.),
+                        padding: UiRect::all(Val::Px(24.)),
+                        ..default()
+                    },
+                    BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.85)),
+                ))
+                .with_children(|panel| {
+                    panel.spawn((
+                        Text("settings".to_string()),
+                        TextFont {
+                            font: font_handle.clone(),
+                            font_size: 32.,
+                            ..default()
+                        },
+                        TextColor(Color::srgb(0.9, 0.9, 0.9)),
+                    ));
+
+                    panel
+                        .spawn(Node {
+                            column_gap: Val::Px(32.),
+                            ..default()
+                        })
+                        .with_children(|columns| {
+                            columns
+                                .spawn(settings_column())
+                                .with_children(|column| spawn_audio_rows(column, &font_handle));
+                            columns
+                                .spawn(settings_column())
+                                .with_children(|column| spawn_graphics_rows(column, &font_handle));
+                        });
+
+                    spawn_settings_button(panel, &font_handle, "close", SettingsButton::Close);
+                });
+        });
+}
+
+fn settings_column() -> Node {
+    Node {
+        flex_direction: FlexDirection::Column,
+        row_gap: Val::Px(8.),
+        ..default()
+    }
+}
+
+fn spawn_audio_rows(column: &mut ChildSpawnerCommands, font_handle: &Handle<Font>) {
+    spawn_settings_heading(column, font_handle, "audio");
+
+    for (label, slider) in [
+        ("master", VolumeSlider::Master),
+        ("music ", VolumeSlider::Music),
+        ("sfx   ", VolumeSlider::Sfx),
+    ] {
+        spawn_settings_row(column, font_handle, label, SettingsValue::Volume(slider), &[
+            ("-", SettingsButton::Decrease(slider)),
+            ("+", SettingsButton::Increase(slider)),
+        ]);
+    }
+
+    spawn_settings_row(column, font_handle, "music on          ", SettingsValue::MusicEnabled, &[
+        ("toggle", SettingsButton::ToggleMusic),
+    ]);
+    spawn_settings_row(column, font_handle, "mute in background", SettingsValue::MuteOnFocusLoss, &[
+        ("toggle", SettingsButton::ToggleMuteOnFocusLoss),
+    ]);
+
+    spawn_settings_heading(column, font_handle, "controls");
+
+    spawn_settings_row(column, font_handle, "rumble", SettingsValue::Rumble, &[
+        ("-", SettingsButton::DecreaseRumble),
+        ("+", SettingsButton::IncreaseRumble),
+    ]);
+    spawn_settings_row(column, font_handle, "deadzone", SettingsValue::Deadzone, &[
+        ("-", SettingsButton::DecreaseDeadzone),
+        ("+", SettingsButton::IncreaseDeadzone),
+    ]);
+    spawn_settings_row(column, font_handle, "key ramp", SettingsValue::Ramp, &[
+        ("-", SettingsButton::DecreaseRamp),
+        ("+", SettingsButton::IncreaseRamp),
+    ]);
+
+    spawn_settings_heading(column, font_handle, "gameplay");
+
+    spawn_settings_row(column, font_handle, "survival", SettingsValue::Survival, &[
+        ("toggle", SettingsButton::ToggleSurvival),
+    ]);
+    spawn_settings_row(column, font_handle, "recover ", SettingsValue::AutoRecover, &[
+        ("toggle", SettingsButton::ToggleAutoRecover),
+    ]);
+    spawn_settings_row(column, font_handle, "respawn ", SettingsValue::Respawn, &[
+        ("toggle", SettingsButton::ToggleRespawn),
+    ]);
+}
+
+fn spawn_graphics_rows(column: &mut ChildSpawnerCommands, font_handle: &Handle<Font>) {
+    spawn_settings_heading(column, font_handle, "graphics");
+
+    spawn_settings_row(column, font_handle, "quality", SettingsValue::Preset, &[
+        ("next", SettingsButton::CyclePreset),
+    ]);
+    spawn_settings_row(column, font_handle, "shadows", SettingsValue::Shadows, &[
+        ("toggle", SettingsButton::ToggleShadows),
+    ]);
+    spawn_settings_row(column, font_handle, "msaa   ", SettingsValue::Msaa, &[
+        ("toggle", SettingsButton::CycleMsaa),
+    ]);
+    // Only the browser canvas can render below native resolution
+    if cfg!(target_arch = "wasm32") {
+        spawn_settings_row(column, font_handle, "scale  ", SettingsValue::RenderScale, &[
+            ("-", SettingsButton::DecreaseRenderScale),
+            ("+", SettingsButton::IncreaseRenderScale),
+        ]);
+    } else {
+        spawn_settings_row(column, font_handle, "wires  ", SettingsValue::Wireframe, &[
+            ("toggle", SettingsButton::ToggleWireframe),
+        ]);
+    }
+    spawn_settings_row(column, font_handle, "vsync  ", SettingsValue::Vsync, &[
+        ("toggle", SettingsButton::ToggleVsync),
+    ]);
+    spawn_settings_row(column, font_handle, "fov    ", SettingsValue::Fov, &[
+        ("-", SettingsButton::DecreaseFov),
+        ("+", SettingsButton::IncreaseFov),
+    ]);
+}
+
+fn spawn_settings_heading(column: &mut ChildSpawnerCommands, font_handle: &Handle<Font>, heading: &str) {
+    column.spawn((
+        Text(heading.to_string()),
+        TextFont {
+            font: font_handle.clone(),
+            font_size: 24.,
+            ..default()
+        },
+        TextColor(Color::srgb_u8(0xAB, 0x69, 0xE7)),
+    ));
+}
+
+fn spawn_settings_row(
+    parent: &mut ChildSpawnerCommands,
+    font_handle: &Handle<Font>,
+    label: &str,
+    value: SettingsValue,
+    buttons: &[(&str, SettingsButton)],
+) {
+    parent
+        .spawn(Node {
+            column_gap: Val::Px(12.),
+            align_items: AlignItems::Center,
+            ..default()
+        })
+        .with_children(|row| {
+            row.spawn((
+                Text(label.to_string()),
+                TextFont {
+                    font: font_handle.clone(),
+                    font_size: 20.,
+                    ..default()
+                },
+                TextColor(Color::srgb(0.9, 0.9, 0.9)),
+            ));
+            row.spawn((
+                Text::default(),
+                TextFont {
+                    font: font_handle.clone(),
+                    font_size: 20.,
+                    ..default()
+                },
+                TextColor(Color::srgb_u8(0xAB, 0x69, 0xE7)),
+                value,
+            ));
+            for (text, button) in buttons {
+                spawn_settings_button(row, font_handle, text, *button);
+            }
+        });
+}
+
+fn spawn_settings_button(parent: &mut ChildSpawnerCommands, font_handle: &Handle<Font>, text: &str, button: SettingsButton) {
+    parent.spawn((Button, button)).with_children(|button| {
+        button.spawn((
+            Text(text.to_string()),
+            TextFont {
+                font: font_handle.clone(),
+                font_size: 20.,
+                ..default()
+            },
+            TextColor(Color::srgb(0.9, 0.9, 0.9)),
+        ));
+    });
+}
+
+fn handle_settings_buttons(
+    mut commands: Commands,
+    interaction_query: Query<(&Interaction, &SettingsButton, &Children), Changed<Interaction>>,
+    mut text_color_query: Query<&mut TextColor>,
+    panel_query: Query<Entity, With<SettingsPanel>>,
+    mut settings: ResMut<AudioSettings>,
+    mut graphics: ResMut<GraphicsSettings>,
+    mut controls: ResMut<ControlSettings>,
+    mut gameplay: ResMut<GameplaySettings>,
+) {
+    for (interaction, button, children) in interaction_query.iter() {
+        if let Some(child) = children.first().copied()
+            && let Ok(mut text_color) = text_color_query.get_mut(child)
+        {
+            *text_color = match *interaction {
+                Interaction::Hovered | Interaction::Pressed => TextColor(Color::srgb_u8(0xAB, 0x69, 0xE7)),
+                Interaction::None => TextColor(Color::srgb(0.9, 0.9, 0.9)),
+            };
+        }
+
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        match *button {
+            SettingsButton::Decrease(slider) => {
+                let volume = settings.volume_mut(slider);
+                *volume = (*volume - VOLUME_STEP).max(0.0);
+            }
+            SettingsButton::Increase(slider) => {
+                let volume = settings.volume_mut(slider);
+                *volume = (*volume + VOLUME_STEP).min(1.0);
+            }
+            SettingsButton::ToggleMusic => settings.music_enabled = !settings.music_enabled,
+            SettingsButton::ToggleMuteOnFocusLoss => settings.mute_on_focus_loss = !settings.mute_on_focus_loss,
+            SettingsButton::CyclePreset => {
+                let preset = graphics.preset.next();
+                graphics.apply_preset(preset);
+            }
+            // Changing any of the preset values by hand leaves the preset behind
+            SettingsButton::ToggleShadows => {
+                graphics.shadows = !graphics.shadows;
+                graphics.preset = GraphicsPreset::Custom;
+            }
+            SettingsButton::CycleMsaa => {
+                graphics.msaa_samples = if graphics.msaa_samples > 1 { 1 } else { 4 };
+                graphics.preset = GraphicsPreset::Custom;
+            }
+            SettingsButton::DecreaseRenderScale => {
+                graphics.render_scale = (graphics.render_scale - RENDER_SCALE_STEP).max(0.25);
+                graphics.preset = GraphicsPreset::Custom;
+            }
+            SettingsButton::IncreaseRenderScale => {
+                graphics.render_scale = (graphics.render_scale + RENDER_SCALE_STEP).min(1.0);
+                graphics.preset = GraphicsPreset::Custom;
+            }
+            SettingsButton::ToggleWireframe => graphics.wireframe = !graphics.wireframe,
+            SettingsButton::ToggleVsync => graphics.vsync = !graphics.vsync,
+            SettingsButton::DecreaseFov => graphics.fov = (graphics.fov - FOV_STEP).max(MIN_FOV),
+            SettingsButton::IncreaseFov => graphics.fov = (graphics.fov + FOV_STEP).min(MAX_FOV),
+            SettingsButton::DecreaseRumble => controls.rumble = (controls.rumble - VOLUME_STEP).max(0.0),
+            SettingsButton::IncreaseRumble => controls.rumble = (controls.rumble + VOLUME_STEP).min(1.0),
+            SettingsButton::DecreaseDeadzone => {
+                controls.trigger_deadzone = (controls.trigger_deadzone - DEADZONE_STEP).max(0.0);
+            }
+            SettingsButton::IncreaseDeadzone => {
+                controls.trigger_deadzone = (controls.trigger_deadzone + DEADZONE_STEP).min(MAX_DEADZONE);
+            }
+            SettingsButton::DecreaseRamp => controls.keyboard_ramp = (controls.keyboard_ramp - RAMP_STEP).max(0.0),
+            SettingsButton::IncreaseRamp => controls.keyboard_ramp = (controls.keyboard_ramp + RAMP_STEP).min(MAX_RAMP),
+            SettingsButton::ToggleSurvival => gameplay.survival = !gameplay.survival,
+            SettingsButton::ToggleAutoRecover => gameplay.auto_recover = !gameplay.auto_recover,
+            SettingsButton::ToggleRespawn => gameplay.respawn = !gameplay.respawn,
+            SettingsButton::Close => {
+                for panel in panel_query.iter() {
+                    commands.entity(panel).despawn();
+                }
+            }
+        }
+    }
+}
+
+fn update_settings_text(
+    settings: Res<AudioSettings>,
+    graphics: Res<GraphicsSettings>,
+    controls: Res<ControlSettings>,
+    gameplay: Res<GameplaySettings>,
+    added_query: Query<(), Added<SettingsValue>>,
+    mut value_query: Query<(&SettingsValue, &mut Text)>,
+) {
+    if !settings.is_changed()
+        && !graphics.is_changed()
+        && !controls.is_changed()
+        && !gameplay.is_changed()
+        && added_query.is_empty()
+    {
+        return;
+    }
+
+    for (value, mut text) in value_query.iter_mut() {
+        text.0 = match *value {
+            SettingsValue::Volume(slider) => {
+                let volume = match slider {
+                    VolumeSlider::Master => settings.master,
+                    VolumeSlider::Music => settings.music,
+                    VolumeSlider::Sfx => settings.sfx,
+                };
+                level_bar(volume)
+            }
+            SettingsValue::MusicEnabled => on_off(settings.music_enabled).to_string(),
+            SettingsValue::MuteOnFocusLoss => on_off(settings.mute_on_focus_loss).to_string(),
+            SettingsValue::Preset => format!("{:<6}", graphics.preset.name()),
+            SettingsValue::Shadows => on_off(graphics.shadows).to_string(),
+            SettingsValue::Msaa => if graphics.msaa_samples > 1 { format!("{}x ", graphics.msaa_samples) } else { "off".to_string() },
+            SettingsValue::RenderScale => format!("{:>3}%", (graphics.render_scale * 100.0).round() as u32),
+            SettingsValue::Wireframe => on_off(graphics.wireframe).to_string(),
+            SettingsValue::Vsync => on_off(graphics.vsync).to_string(),
+            SettingsValue::Fov => format!("{:>3}°", graphics.fov.round() as u32),
+            SettingsValue::Rumble => level_bar(controls.rumble),
+            SettingsValue::Deadzone => format!("{:>3}%", (controls.trigger_deadzone * 100.0).round() as u32),
+            // Half a step so float drift from the buttons still reads as off
+            SettingsValue::Ramp => if controls.keyboard_ramp < RAMP_STEP * 0.5 {
+                "off ".to_string()
+            } else {
+                format!("{:.1}s", controls.keyboard_ramp)
+            },
+            SettingsValue::Survival => on_off(gameplay.survival).to_string(),
+            SettingsValue::AutoRecover => on_off(gameplay.auto_recover).to_string(),
+            SettingsValue::Respawn => on_off(gameplay.respawn).to_string(),
+        };
+    }
+}
+
+fn level_bar(level: f32) -> String {
+    // Ten step bar, rounded so float drift from the buttons doesn't show
+    let steps = ((level / VOLUME_STEP).round() as usize).min(10);
+    format!("[{}{}] {:>3}%", "#".repeat(steps), "-".repeat(10 - steps), steps * 10)
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "on " } else { "off" }
+}
+
+fn track_window_focus(mut focus_events: MessageReader<WindowFocused>, mut focus: ResMut<WindowFocus>) {
+    for event in focus_events.read() {
+        focus.0 = event.focused;
+    }
+}
+
+fn apply_audio_settings(
+    settings: Res<AudioSettings>,
+    focus: Res<WindowFocus>,
+    music_channel: Res<AudioChannel<MusicChannel>>,
+    sfx_channel: Res<AudioChannel<SfxChannel>>,
+) {
+    if !settings.is_changed() && !focus.is_changed() {
+        return;
+    }
+
+    let focus_volume = if settings.mute_on_focus_loss && !focus.0 { 0.0 } else { 1.0 };
+    music_channel.set_volume(synth::decibels(settings.music_volume() * focus_volume));
+    sfx_channel.set_volume(synth::decibels(settings.sfx_volume() * focus_volume));
+}
@@ -0,0 +1,275 @@
+use bevy::{camera::Viewport, prelude::*, window::PrimaryWindow};
+use bevy_rapier3d::prelude::Velocity;
+use leafwing_input_manager::prelude::InputMap;
+
+use crate::{
+    camera::PlayerCamera,
+    car::{PlayerCar, PlayerOut},
+    input::CarAction,
+    net::NetMode,
+    run::{LastRun, RunStats},
+    state::GameState,
+};
+
+pub struct SplitScreenPlugin;
+
+impl Plugin for SplitScreenPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SplitScreen::from_args())
+            .add_systems(OnEnter(GameState::Running), spawn_ui_camera.run_if(split_screen_enabled))
+            .add_systems(
+                Update,
+                (update_viewports, assign_gamepads, spawn_player_huds, update_player_huds)
+                    .run_if(split_screen_enabled)
+                    .run_if(in_state(GameState::Running)),
+            )
+            .add_systems(OnExit(GameState::Running), despawn_player_huds);
+    }
+}
+
+/// How many players share the screen, each gets a car, a camera and a gamepad
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct SplitScreen {
+    pub players: usize,
+}
+
+impl SplitScreen {
+    /// Two players with `--split-screen`, the browser build is always single player.
+    /// A dedicated server has nobody at the screen, and only one player per client is networked
+    fn from_args() -> Self {
+        let net_mode = NetMode::from_args();
+        if net_mode.is_server() {
+            return Self { players: 0 };
+        }
+        let split = !cfg!(target_arch = "wasm32")
+            && !net_mode.is_client()
+            && std::env::args().any(|arg| arg == "--split-screen");
+        Self {
+            players: if split { 2 } else { 1 },
+        }
+    }
+}
+
+fn split_screen_enabled(split_screen: Res<SplitScreen>) -> bool {
+    split_screen.players > 1
+}
+
+/// Draws shared UI (controls, settings, game over) over the whole window rather than one player's half
+#[derive(Component)]
+struct SplitScreenUiCamera;
+
+/// A player's own readout, drawn in their half of the screen
+#[derive(Component)]
+struct PlayerHud(usize);
+
+#[derive(Component)]
+struct PlayerHudText(usize);
+
+/// One line of HUD text following a player, see `spawn_player_text_huds`
+#[derive(Component, Debug, Clone, Copy)]
+pub struct HudLine {
+    pub player: usize,
+    /// The camera it was laid out for, cameras are replaced every run
+    camera: Entity,
+}
+
+fn spawn_ui_camera(mut commands: Commands, ui_camera_query: Query<(), With<SplitScreenUiCamera>>) {
+    if !ui_camera_query.is_empty() {
+        return;
+    }
+
+    commands.spawn((
+        Camera2d,
+        Camera {
+            // On top of both player views, without clearing them
+            order: 10,
+            clear_color: ClearColorConfig::None,
+            ..default()
+        },
+        IsDefaultUiCamera,
+        SplitScreenUiCamera,
+    ));
+}
+
+/// Side by side views, kept in step with the window size
+fn update_viewports(
+    split_screen: Res<SplitScreen>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mut camera_query: Query<(&mut Camera, &PlayerCamera)>,
+) {
+    let Ok(window) = window_query.single() else {
+        return;
+    };
+    let size = window.physical_size();
+    // Minimised windows have no size, and a zero sized viewport can't be rendered
+    if size.x == 0 || size.y == 0 {
+        return;
+    }
+
+    let width = size.x / split_screen.players as u32;
+    for (mut camera, player) in camera_query.iter_mut() {
+        let position = UVec2::new(width * player.0 as u32, 0);
+        let viewport_size = UVec2::new(width, size.y);
+        let unchanged = camera
+            .viewport
+            .as_ref()
+            .is_some_and(|viewport| viewport.physical_position == position && viewport.physical_size == viewport_size);
+        if !unchanged {
+            camera.viewport = Some(Viewport {
+                physical_position: position,
+                physical_size: viewport_size,
+                ..default()
+            });
+        }
+    }
+}
+
+/// Bind each player to their own gamepad, in the order the gamepads connected
+fn assign_gamepads(
+    gamepad_query: Query<Entity, With<Gamepad>>,
+    mut player_query: Query<(&PlayerCar, &mut InputMap<CarAction>)>,
+) {
+    let mut gamepads: Vec<Entity> = gamepad_query.iter().collect();
+    gamepads.sort();
+
+    for (player, mut input_map) in player_query.iter_mut() {
+        // With no gamepad of their own a player must not fall back to reading every gamepad,
+        // that would steer someone else's car too. A placeholder gamepad reads nothing
+        let gamepad = gamepads.get(player.0).copied().unwrap_or(Entity::PLACEHOLDER);
+        if input_map.gamepad() != Some(gamepad) {
+            if gamepad != Entity::PLACEHOLDER {
+                bevy::log::info!("Player {} is on gamepad {}", player.0 + 1, gamepad);
+            }
+            input_map.set_gamepad(gamepad);
+        }
+    }
+}
+
+fn spawn_player_huds(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    camera_query: Query<(Entity, &PlayerCamera)>,
+    hud_query: Query<&PlayerHud>,
+) {
+    for (camera, player) in camera_query.iter() {
+        if hud_query.iter().any(|hud| hud.0 == player.0) {
+            continue;
+        }
+
+        let font_handle = asset_server.load("font/NotoSansMono-Bold.ttf");
+        commands
+            .spawn((
+                Node {
+                    width: Val::Percent(100.),
+                    height: Val::Percent(100.),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::FlexEnd,
+                    padding: UiRect::bottom(Val::Px(20.)),
+                    ..default()
+                },
+                // Laid out inside this player's viewport
+                UiTargetCamera(camera),
+                PlayerHud(player.0),
+            ))
+            .with_children(|parent| {
+                parent.spawn((
+                    Text::default(),
+                    TextFont {
+                        font: font_handle,
+                        font_size: 20.,
+                        ..default()
+                    },
+                    TextColor(Color::srgb(0.9, 0.9, 0.9)),
+                    PlayerHudText(player.0),
+                ));
+            });
+    }
+}
+
+fn update_player_huds(
+    last_run: Res<LastRun>,
+    car_query: Query<(&PlayerCar, &Velocity, &RunStats, Has<PlayerOut>)>,
+    mut text_query: Query<(&mut Text, &mut TextColor, &PlayerHudText)>,
+) {
+    for (mut text, mut text_color, hud) in text_query.iter_mut() {
+        let Some((_, velocity, stats, out)) = car_query.iter().find(|(player, ..)| player.0 == hud.0) else {
+            continue;
+        };
+
+        let label = if out {
+            // This player's run is over, the other keeps going
+            *text_color = TextColor(Color::srgb_u8(0xAB, 0x69, 0xE7));
+            let reason = last_run
+                .0
+                .iter()
+                .find(|run| run.player == hud.0)
+                .map(|run| run.reason.title().to_lowercase())
+                .unwrap_or_default();
+            format!("player {} out, {}", hud.0 + 1, reason)
+        } else {
+            *text_color = TextColor(Color::srgb(0.9, 0.9, 0.9));
+            format!(
+                "player {}   {:>3.0} km/h   {:.1} km",
+                hud.0 + 1,
+                velocity.linvel.length() * 3.6,
+                stats.distance / 1000.0
+            )
+        };
+        if text.0 != label {
+            text.0 = label;
+        }
+    }
+}
+
+/// Give each player camera one line of HUD text marked with `marker`, placed by `node` within
+/// that player's view when the screen is split. Meant to be called every frame while running,
+/// lines laid out for cameras from an earlier run are swapped for new ones
+pub fn spawn_player_text_huds<T: Component + Clone>(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    camera_query: &Query<(Entity, &PlayerCamera)>,
+    hud_query: &Query<(&HudLine, &ChildOf), With<T>>,
+    node: Node,
+    color: Color,
+    marker: T,
+) {
+    for (hud, child_of) in hud_query.iter() {
+        if !camera_query.contains(hud.camera) {
+            commands.entity(child_of.parent()).despawn();
+        }
+    }
+
+    // A camera per player, more than one means the screen is split
+    let split = camera_query.iter().count() > 1;
+    for (camera, player) in camera_query.iter() {
+        if hud_query.iter().any(|(hud, _)| hud.camera == camera) {
+            continue;
+        }
+
+        let font_handle = asset_server.load("font/NotoSansMono-Bold.ttf");
+        let mut hud = commands.spawn(node.clone());
+        if split {
+            hud.insert(UiTargetCamera(camera));
+        }
+        hud.with_children(|parent| {
+            parent.spawn((
+                Text::default(),
+                TextFont {
+                    font: font_handle,
+                    font_size: 16.,
+                    ..default()
+                },
+                TextColor(color),
+                HudLine { player: player.0, camera },
+                marker.clone(),
+            ));
+        });
+    }
+}
+
+fn despawn_player_huds(mut commands: Commands, hud_query: Query<Entity, With<PlayerHud>>) {
+    // The cameras they target are replaced when the game starts again
+    for entity in hud_query.iter() {
+        commands.entity(entity).despawn();
+    }
+}
@@ -0,0 +1,57 @@
+use serde::Serialize;
+
+// Each key is a file under the config directory on native,
+// and in localStorage in the browser
+#[cfg(not(target_arch = "wasm32"))]
+mod backend {
+    use std::{fs, path::PathBuf};
+
+    fn path(key: &str) -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("desertcar").join(format!("{key}.json")))
+    }
+
+    pub fn load(key: &str) -> Option<String> {
+        fs::read_to_string(path(key)?).ok()
+    }
+
+    pub fn save(key: &str, value: &str) -> Result<(), String> {
+        let path = path(key).ok_or("no config directory")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|error| error.to_string())?;
+        }
+        fs::write(path, value).map_err(|error| error.to_string())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod backend {
+    fn local_storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok()?
+    }
+
+    fn storage_key(key: &str) -> String {
+        format!("desertcar.{key}")
+    }
+
+    pub fn load(key: &str) -> Option<String> {
+        local_storage()?.get_item(&storage_key(key)).ok()?
+    }
+
+    pub fn save(key: &str, value: &str) -> Result<(), String> {
+        local_storage()
+            .ok_or("localStorage unavailable")?
+            .set_item(&storage_key(key), value)
+            .map_err(|_| "localStorage write failed".to_string())
+    }
+}
+
+pub use backend::{load, save};
+
+pub fn save_json<T: Serialize>(key: &str, value: &T) {
+    let result = serde_json::to_string(value)
+        .map_err(|error| error.to_string())
+        .and_then(|raw| save(key, &raw));
+    if let Err(error) = result {
+        bevy::log::warn!("Failed to save {}: {}", key, error);
+    }
+}
@@ -0,0 +1,275 @@
+use bevy::{mesh::VertexAttributeValues, prelude::*};
+use bevy_rapier3d::{
+    parry::shape::FeatureId,
+    prelude::{QueryFilter, ReadRapierContext},
+};
+
+use crate::{car::Car, movement::apply_movement, rng::Rng, state::GameState};
+
+pub struct SurfacePlugin;
+
+impl Plugin for SurfacePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TerrainSurfaces>().add_systems(
+            Update,
+            update_ground_surface
+                .before(apply_movement)
+                .run_if(in_state(GameState::Running)),
+        );
+    }
+}
+
+/// The kinds of ground the desert is made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Surface {
+    #[default]
+    PackedSand,
+    SoftSand,
+    Rock,
+    SaltFlat,
+    Quicksand,
+}
+
+/// How a surface changes the way the car drives and what it kicks up
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceProperties {
+    /// Multiplier on lateral tire grip
+    pub grip: f32,
+    /// Multiplier on longitudinal drag
+    pub rolling_resistance: f32,
+    /// How much dust the wheels throw up, 0 for none
+    pub dust: f32,
+    pub dust_color: Color,
+}
+
+impl Surface {
+    pub const ALL: [Surface; 5] = [
+        Self::PackedSand,
+        Self::SoftSand,
+        Self::Rock,
+        Self::SaltFlat,
+        Self::Quicksand,
+    ];
+
+    pub fn properties(&self) -> SurfaceProperties {
+        match self {
+            Self::PackedSand => SurfaceProperties {
+                grip: 1.0,
+                rolling_resistance: 1.0,
+                dust: 0.6,
+                dust_color: Color::srgb(0.55, 0.35, 0.2),
+            },
+            Self::SoftSand => SurfaceProperties {
+                grip: 0.7,
+                rolling_resistance: 1.6,
+                dust: 1.0,
+                dust_color: Color::srgb(0.65, 0.45, 0.25),
+            },
+            Self::Rock => SurfaceProperties {
+                grip: 1.2,
+                rolling_resistance: 0.9,
+                dust: 0.1,
+                dust_color: Color::srgb(0.4, 0.35, 0.3),
+            },
+            Self::SaltFlat => SurfaceProperties {
+                grip: 0.55,
+                rolling_resistance: 0.7,
+                dust: 0.3,
+                dust_color: Color::srgb(0.85, 0.85, 0.8),
+            },
+            Self::Quicksand => SurfaceProperties {
+                grip: 0.4,
+                rolling_resistance: 4.0,
+                dust: 0.2,
+                dust_color: Color::srgb(0.45, 0.3, 0.15),
+            },
+        }
+    }
+
+    /// Surfaces can be authored in Blender by naming the terrain material after them
+    pub fn from_material_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().replace([' ', '-'], "_").as_str() {
+            "PACKED_SAND" => Some(Self::PackedSand),
+            "SOFT_SAND" => Some(Self::SoftSand),
+            "ROCK" => Some(Self::Rock),
+            "SALT_FLAT" => Some(Self::SaltFlat),
+            "QUICKSAND" => Some(Self::Quicksand),
+            _ => None,
+        }
+    }
+
+    /// Vertex color painted for each surface when authoring with color attributes
+    fn paint_color(&self) -> Vec3 {
+        match self {
+            Self::PackedSand => Vec3::new(1.0, 1.0, 1.0),
+            Self::SoftSand => Vec3::new(1.0, 1.0, 0.0),
+            Self::Rock => Vec3::new(0.0, 0.0, 0.0),
+            Self::SaltFlat => Vec3::new(0.0, 1.0, 1.0),
+            Self::Quicksand => Vec3::new(1.0, 0.0, 0.0),
+        }
+    }
+
+    /// Pick the surface whose paint color is closest to the given vertex color
+    pub fn from_vertex_color(color: Vec3) -> Self {
+        Self::ALL
+            .into_iter()
+            .min_by(|a, b| {
+                a.paint_color()
+                    .distance_squared(color)
+                    .total_cmp(&b.paint_color().distance_squared(color))
+            })
+            .unwrap_or_default()
+    }
+
+    /// Fallback for unpainted terrain: rock on steep slopes, soft sand up on the high dunes, and
+    /// seeded patches of everything else across the open ground so the whole desert isn't one surface
+    fn from_geometry(normal: Vec3, centroid: Vec3) -> Self {
+        if normal.y < 0.75 {
+            return Self::Rock;
+        }
+        if centroid.y > 60.0 {
+            return Self::SoftSand;
+        }
+
+        let cell_x = (centroid.x / PATCH_SIZE).floor() as i32 as u32 as u64;
+        let cell_z = (centroid.z / PATCH_SIZE).floor() as i32 as u32 as u64;
+        let roll = Rng::new(PATCH_SEED ^ (cell_x << 32) ^ cell_z).next_f32();
+        // Salt and quicksand collect in the flat low basins, higher ground is sand and rock
+        let basin = centroid.y < 2.0 && normal.y > 0.98;
+        match (basin, roll) {
+            (true, roll) if roll < 0.08 => Self::Quicksand,
+            (true, roll) if roll < 0.4 => Self::SaltFlat,
+            (_, roll) if roll < 0.55 => Self::SoftSand,
+            (_, roll) if roll < 0.62 => Self::Rock,
+            _ => Self::PackedSand,
+        }
+    }
+}
+
+// Unpainted terrain is split into square patches this wide (m), each rolling its own surface
+const PATCH_SIZE: f32 = 150.0;
+const PATCH_SEED: u64 = 0x5A4D_F1A7;
+
+/// Marks the desert collider, the only thing whose triangles have surfaces
+#[derive(Component, Debug)]
+pub struct Terrain;
+
+/// Surface type of every triangle in the terrain collider, in collider triangle order
+#[derive(Resource, Debug, Default)]
+pub struct TerrainSurfaces {
+    pub triangles: Vec<Surface>,
+}
+
+impl TerrainSurfaces {
+    /// Classify each terrain triangle from vertex colors if the mesh has them,
+    /// otherwise from the material name, otherwise from the triangle's shape
+    pub fn classify(mesh: &Mesh, vertices: &[Vec3], indices: &[[u32; 3]], material_name: Option<&str>) -> Self {
+        let colors: Option<Vec<Vec3>> = match mesh.attribute(Mesh::ATTRIBUTE_COLOR) {
+            Some(VertexAttributeValues::Float32x4(values)) => {
+                Some(values.iter().map(|c| Vec3::new(c[0], c[1], c[2])).collect())
+            }
+            Some(VertexAttributeValues::Float32x3(values)) => {
+                Some(values.iter().map(|c| Vec3::from_array(*c)).collect())
+            }
+            _ => None,
+        };
+        let material_surface = material_name.and_then(Surface::from_material_name);
+
+        let triangles = indices
+            .iter()
+            .map(|triangle| {
+                let [a, b, c] = triangle.map(|i| i as usize);
+                if let Some(colors) = &colors {
+                    let average = (colors[a] + colors[b] + colors[c]) / 3.0;
+                    Surface::from_vertex_color(average)
+                } else if let Some(surface) = material_surface {
+                    surface
+                } else {
+                    let normal = (vertices[b] - vertices[a])
+                        .cross(vertices[c] - vertices[a])
+                        .normalize_or_zero();
+                    let centroid = (vertices[a] + vertices[b] + vertices[c]) / 3.0;
+                    Surface::from_geometry(normal.abs(), centroid)
+                }
+            })
+            .collect();
+
+        Self { triangles }
+    }
+
+    pub fn get(&self, feature: FeatureId) -> Surface {
+        match feature {
+            // Back faces of a trimesh are reported past the end of the triangle list
+            FeatureId::Face(index) if !self.triangles.is_empty() => {
+                self.triangles[index as usize % self.triangles.len()]
+            }
+            _ => Surface::default(),
+        }
+    }
+}
+
+/// The surface currently under a car
+#[derive(Component, Debug, Default)]
+pub struct GroundSurface {
+    pub surface: Surface,
+}
+
+fn update_ground_surface(
+    read_rapier_context: ReadRapierContext,
+    terrain_surfaces: Res<TerrainSurfaces>,
+    terrain_query: Query<(), With<Terrain>>,
+    mut car_query: Query<(Entity, &GlobalTransform, &mut GroundSurface), With<Car>>,
+) {
+    let Ok(rapier_context) = read_rapier_context.single() else {
+        return;
+    };
+
+    for (car_entity, global_transform, mut ground_surface) in car_query.iter_mut() {
+        // Look straight down from the car body for the triangle the wheels are on
+        let origin = global_transform.translation();
+        let filter = QueryFilter::default().exclude_rigid_body(car_entity).exclude_sensors();
+        if let Some((entity, hit)) = rapier_context.cast_ray_and_get_normal(origin, Vec3::NEG_Y, 5.0, true, filter) {
+            // Another car or a wheel underneath says nothing about the ground, keep the last surface
+            if !terrain_query.contains(entity) {
+                continue;
+            }
+            let surface = terrain_surfaces.get(hit.feature);
+            if ground_surface.surface != surface {
+                bevy::log::debug!("Car surface changed to {:?}", surface);
+                ground_surface.surface = surface;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn material_names_are_read_loosely() {
+        assert_eq!(Surface::from_material_name("PACKED_SAND"), Some(Surface::PackedSand));
+        assert_eq!(Surface::from_material_name("soft sand"), Some(Surface::SoftSand));
+        assert_eq!(Surface::from_material_name("Salt-Flat"), Some(Surface::SaltFlat));
+        assert_eq!(Surface::from_material_name("rock"), Some(Surface::Rock));
+        assert_eq!(Surface::from_material_name("Quicksand"), Some(Surface::Quicksand));
+        assert_eq!(Surface::from_material_name("Material.001"), None);
+        assert_eq!(Surface::from_material_name(""), None);
+    }
+
+    #[test]
+    fn paint_colors_map_back_to_their_surface() {
+        for surface in Surface::ALL {
+            assert_eq!(Surface::from_vertex_color(surface.paint_color()), surface);
+        }
+    }
+
+    #[test]
+    fn vertex_color_picks_the_closest_paint() {
+        // Slightly off paint, as blended by the exporter
+        assert_eq!(Surface::from_vertex_color(Vec3::new(0.9, 0.95, 0.1)), Surface::SoftSand);
+        assert_eq!(Surface::from_vertex_color(Vec3::new(0.1, 0.05, 0.1)), Surface::Rock);
+        assert_eq!(Surface::from_vertex_color(Vec3::new(0.8, 0.1, 0.2)), Surface::Quicksand);
+        assert_eq!(Surface::from_vertex_color(Vec3::new(0.2, 0.9, 0.85)), Surface::SaltFlat);
+    }
+}
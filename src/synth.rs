@@ -0,0 +1,187 @@
+use std::{f32::consts::TAU, sync::Arc};
+
+use bevy_kira_audio::AudioSource;
+use kira::{
+    Decibels, Frame,
+    sound::static_sound::{StaticSoundData, StaticSoundSettings},
+};
+
+use crate::rng::Rng;
+
+// Matches the lofi music track, plenty for noise and engine hum
+pub const SAMPLE_RATE: u32 = 22050;
+
+/// Wrap mono samples into an audio source bevy_kira_audio can play
+pub fn audio_source(samples: Vec<f32>) -> AudioSource {
+    let frames: Arc<[Frame]> = samples.into_iter().map(Frame::from_mono).collect();
+    AudioSource {
+        sound: StaticSoundData {
+            sample_rate: SAMPLE_RATE,
+            frames,
+            settings: StaticSoundSettings::default(),
+            slice: None,
+        },
+    }
+}
+
+/// Convert a linear amplitude (0 silent, 1 unchanged) to kira's decibel volume
+pub fn decibels(amplitude: f32) -> Decibels {
+    if amplitude <= 0.0001 {
+        Decibels::SILENCE
+    } else {
+        Decibels(20.0 * amplitude.log10())
+    }
+}
+
+fn sample_count(seconds: f32) -> usize {
+    (seconds * SAMPLE_RATE as f32) as usize
+}
+
+/// Howling wind: low-passed noise with a few slow gusts, loops cleanly since the
+/// gusts fit a whole number of cycles into the length
+pub fn wind(seconds: f32, seed: u64) -> Vec<f32> {
+    let mut rng = Rng::new(seed);
+    let count = sample_count(seconds);
+    let mut low = 0.0;
+
+    (0..count)
+        .map(|i| {
+            let t = i as f32 / count as f32;
+            let gust = 0.6 + 0.25 * (t * TAU * 3.0).sin() + 0.15 * (t * TAU * 7.0).sin();
+            // One-pole low pass turns white noise into a rumble
+            low += (rng.range(-1.0, 1.0) - low) * 0.08;
+            low * gust * 2.5
+        })
+        .collect()
+}
+
+/// Engine hum at a fixed pitch, sped up and slowed down at runtime to follow the revs.
+/// One second of a 55 Hz tone is a whole number of cycles, so the loop is seamless
+pub fn engine(seed: u64) -> Vec<f32> {
+    let mut rng = Rng::new(seed);
+    let count = sample_count(1.0);
+    let base = 55.0;
+
+    (0..count)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let phase = t * base * TAU;
+            // A handful of harmonics and some grit for the exhaust
+            let tone = phase.sin() * 0.5
+                + (phase * 2.0).sin() * 0.3
+                + (phase * 3.0).sin() * 0.15
+                + (phase * 4.0).sin() * 0.08;
+            let firing = 0.8 + 0.2 * (phase * 0.5).sin().abs();
+            (tone * firing + rng.range(-0.05, 0.05)) * 0.6
+        })
+        .collect()
+}
+
+/// Heavy thump for landings, a falling low tone over a burst of noise
+pub fn thud(seed: u64) -> Vec<f32> {
+    let mut rng = Rng::new(seed);
+    let count = sample_count(0.4);
+    let mut phase = 0.0;
+    let mut low = 0.0;
+
+    (0..count)
+        .map(|i| {
+            let t = i as f32 / count as f32;
+            let frequency = 70.0 - 35.0 * t;
+            phase += frequency * TAU / SAMPLE_RATE as f32;
+            low += (rng.range(-1.0, 1.0) - low) * 0.1;
+            let envelope = (1.0 - t).powi(3);
+            (phase.sin() * 0.8 + low * 0.6) * envelope
+        })
+        .collect()
+}
+
+/// Crunch for hard collisions, bright noise that decays fast
+pub fn crash(seed: u64) -> Vec<f32> {
+    let mut rng = Rng::new(seed);
+    let count = sample_count(0.5);
+    let mut low = 0.0;
+
+    (0..count)
+        .map(|i| {
+            let t = i as f32 / count as f32;
+            let noise = rng.range(-1.0, 1.0);
+            low += (noise - low) * 0.3;
+            let envelope = (1.0 - t).powi(4);
+            (noise * 0.4 + low * 0.6) * envelope
+        })
+        .collect()
+}
+
+/// Tire squeal, a wavering high tone with some hiss
+pub fn squeal(seed: u64) -> Vec<f32> {
+    let mut rng = Rng::new(seed);
+    let count = sample_count(0.6);
+    let mut phase = 0.0;
+
+    (0..count)
+        .map(|i| {
+            let t = i as f32 / count as f32;
+            let frequency = 1400.0 + 120.0 * (t * TAU * 9.0).sin();
+            phase += frequency * TAU / SAMPLE_RATE as f32;
+            // Quick attack, long tail
+            let envelope = (t * 20.0).min(1.0) * (1.0 - t).powi(2);
+            (phase.sin() * 0.3 + rng.range(-0.1, 0.1)) * envelope
+        })
+        .collect()
+}
+
+/// Bright two-note chime for collecting a pickup, pitched by the caller per kind
+pub fn chime() -> Vec<f32> {
+    let count = sample_count(0.5);
+    let split = count / 4;
+
+    (0..count)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            // A quick grace note then the main note a fifth up
+            let frequency = if i < split { 880.0 } else { 1320.0 };
+            let local = if i < split { i } else { i - split } as f32;
+            let length = if i < split { split } else { count - split } as f32;
+            let envelope = (local / 200.0).min(1.0) * (1.0 - local / length).powi(2);
+            ((t * frequency * TAU).sin() * 0.5 + (t * frequency * 2.0 * TAU).sin() * 0.2) * envelope * 0.6
+        })
+        .collect()
+}
+
+/// Slow desert drone for the drive screen. Both tones fit a whole number of
+/// cycles into the loop, so it repeats without a click
+pub fn drone(seconds: f32) -> Vec<f32> {
+    let count = sample_count(seconds);
+
+    (0..count)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let swell = 0.7 + 0.3 * (i as f32 / count as f32 * TAU * 2.0).sin();
+            let tone = (t * 110.0 * TAU).sin() * 0.5
+                + (t * 165.0 * TAU).sin() * 0.3
+                + (t * 220.0 * TAU).sin() * 0.1;
+            tone * swell * 0.4
+        })
+        .collect()
+}
+
+/// Short falling arpeggio played when a run ends
+pub fn stinger() -> Vec<f32> {
+    let notes = [440.0, 370.0, 330.0, 220.0];
+    let note_length = sample_count(0.22);
+
+    notes
+        .iter()
+        .enumerate()
+        .flat_map(|(n, frequency)| {
+            // The last note rings out longer
+            let length = if n == notes.len() - 1 { note_length * 4 } else { note_length };
+            (0..length).map(move |i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let envelope = (1.0 - i as f32 / length as f32).powi(2);
+                ((t * frequency * TAU).sin() * 0.6 + (t * frequency * 2.0 * TAU).sin() * 0.15) * envelope * 0.5
+            })
+        })
+        .collect()
+}
@@ -1,11 +1,120 @@
+use std::time::Duration;
+
 use bevy::prelude::*;
-use bevy_kira_audio::{Audio, AudioControl, AudioInstance, AudioSource, AudioTween};
+use bevy_kira_audio::{AudioChannel, AudioControl, AudioInstance, AudioSource, AudioTween};
+
+use crate::{
+    rng::Rng,
+    settings::{AudioSettings, MusicChannel},
+    state::GameState,
+    synth,
+};
+
+pub struct ThemePlugin;
+
+impl Plugin for ThemePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NowPlaying>()
+            .add_systems(OnEnter(GameState::Setup), (load, play_drive_screen_music).chain())
+            .add_systems(OnEnter(GameState::Running), (play, spawn_music_toggle, spawn_now_playing_toast))
+            .add_systems(OnEnter(GameState::GameOver), play_game_over_stinger)
+            .add_systems(Update, (advance_playlist, update_now_playing_toast, update_music_toggle_text))
+            .add_systems(Update, handle_music_toggle.run_if(in_state(GameState::Running)));
+    }
+}
+
+const CROSSFADE_SECONDS: f32 = 3.0;
+const NOW_PLAYING_SECONDS: f32 = 4.0;
+// Music drops to this level under the game over screen
+const GAME_OVER_DUCK: f32 = 0.35;
+
+pub struct Track {
+    pub title: &'static str,
+    pub source: Handle<AudioSource>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    /// Stop after the last track
+    Off,
+    /// Keep looping the current track
+    One,
+    /// Start over after the last track
+    All,
+}
+
+/// Tracks played while driving
+#[derive(Resource)]
+pub struct Playlist {
+    pub tracks: Vec<Track>,
+    pub shuffle: bool,
+    pub repeat: Repeat,
+    order: Vec<usize>,
+    position: usize,
+    rng: Rng,
+}
+
+impl Playlist {
+    pub fn new(tracks: Vec<Track>, shuffle: bool, repeat: Repeat) -> Self {
+        let mut playlist = Self {
+            order: (0..tracks.len()).collect(),
+            tracks,
+            shuffle,
+            repeat,
+            position: 0,
+            rng: Rng::default(),
+        };
+        if shuffle {
+            playlist.reshuffle();
+        }
+        playlist
+    }
+
+    pub fn current(&self) -> Option<&Track> {
+        self.order.get(self.position).and_then(|&index| self.tracks.get(index))
+    }
+
+    /// Move to the next track, returns false once a non-repeating playlist runs out
+    pub fn advance(&mut self) -> bool {
+        match self.repeat {
+            Repeat::One => true,
+            Repeat::Off if self.position + 1 >= self.order.len() => false,
+            _ => {
+                self.position += 1;
+                if self.position >= self.order.len() {
+                    self.position = 0;
+                    if self.shuffle {
+                        self.reshuffle();
+                    }
+                }
+                true
+            }
+        }
+    }
+
+    fn reshuffle(&mut self) {
+        // Fisher-Yates
+        for i in (1..self.order.len()).rev() {
+            let j = (self.rng.next_u64() % (i as u64 + 1)) as usize;
+            self.order.swap(i, j);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MusicMode {
+    DriveScreen,
+    Playlist,
+}
 
 #[derive(Resource)]
 pub struct ThemeState {
-    pub loop_handle: Handle<AudioSource>,
     pub instance: Option<Handle<AudioInstance>>,
-    pub is_playing: bool,
+    mode: MusicMode,
+    drive_screen_loop: Handle<AudioSource>,
+    game_over_stinger: Handle<AudioSource>,
+    // Set once the next track has started fading in over the current one
+    crossfading: bool,
 }
 
 #[derive(Component)]
@@ -14,31 +123,187 @@ pub struct MusicToggleButton;
 #[derive(Component)]
 pub struct MusicToggleText;
 
-type ButtonInteractionQuery<'w, 's> = Query<'w, 's, (&'static Interaction, &'static Children), (Changed<Interaction>, With<Button>)>;
+#[derive(Component)]
+struct NowPlayingToast {
+    time_left: f32,
+}
+
+/// Set whenever a new playlist track starts, drives the now playing toast
+#[derive(Resource, Default)]
+struct NowPlaying {
+    title: Option<&'static str>,
+}
+
+pub fn load(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut audio_sources: ResMut<Assets<AudioSource>>,
+    existing_state: Option<Res<ThemeState>>,
+) {
+    if existing_state.is_some() {
+        return;
+    }
+
+    // New tracks go here. Variants from the assets/audio processing pipeline need converting to ogg first,
+    // none ship yet since they need the original recording
+    let tracks = vec![
+        Track {
+            title: "overworld (lofi, half speed)",
+            source: asset_server.load("audio/overworld-lofi-random-halfspeed.ogg"),
+        },
+        Track {
+            title: "dune drone",
+            source: audio_sources.add(synth::audio_source(synth::drone(16.0))),
+        },
+    ];
+    commands.insert_resource(Playlist::new(tracks, false, Repeat::All));
 
-pub fn load(mut commands: Commands, asset_server: ResMut<AssetServer>) {
-    let loop_handle = asset_server.load("audio/overworld-lofi-random-halfspeed.ogg");
     let theme_state = ThemeState {
-        loop_handle,
         instance: None,
-        is_playing: false,
+        mode: MusicMode::DriveScreen,
+        drive_screen_loop: audio_sources.add(synth::audio_source(synth::drone(8.0))),
+        game_over_stinger: audio_sources.add(synth::audio_source(synth::stinger())),
+        crossfading: false,
     };
 
     commands.insert_resource(theme_state);
 }
 
-pub fn play(mut audio_state: ResMut<ThemeState>, audio: Res<Audio>) {
+fn crossfade() -> AudioTween {
+    AudioTween::linear(Duration::from_secs_f32(CROSSFADE_SECONDS))
+}
+
+fn play_drive_screen_music(mut audio_state: ResMut<ThemeState>, music_channel: Res<AudioChannel<MusicChannel>>) {
     if audio_state.instance.is_none() {
-        let instance = audio.play(audio_state.loop_handle.clone()).looped().handle();
+        let instance = music_channel
+            .play(audio_state.drive_screen_loop.clone())
+            .looped()
+            .fade_in(crossfade())
+            .handle();
         audio_state.instance = Some(instance);
-        audio_state.is_playing = true;
-        bevy::log::info!("Music started");
+        audio_state.mode = MusicMode::DriveScreen;
+    }
+}
+
+/// Start a playlist track, fading out whatever was playing before
+fn start_track(
+    audio_state: &mut ThemeState,
+    playlist: &Playlist,
+    now_playing: &mut NowPlaying,
+    music_channel: &AudioChannel<MusicChannel>,
+    audio_instances: &mut Assets<AudioInstance>,
+) {
+    if let Some(instance_handle) = &audio_state.instance
+        && let Some(instance) = audio_instances.get_mut(instance_handle)
+    {
+        instance.stop(crossfade());
+    }
+
+    let Some(track) = playlist.current() else {
+        audio_state.instance = None;
+        return;
+    };
+
+    let mut command = music_channel.play(track.source.clone());
+    if playlist.repeat == Repeat::One {
+        command.looped();
+    }
+    audio_state.instance = Some(command.fade_in(crossfade()).handle());
+    audio_state.mode = MusicMode::Playlist;
+    audio_state.crossfading = false;
+    now_playing.title = Some(track.title);
+    bevy::log::info!("Now playing: {}", track.title);
+}
+
+pub fn play(
+    mut audio_state: ResMut<ThemeState>,
+    playlist: Res<Playlist>,
+    mut now_playing: ResMut<NowPlaying>,
+    music_channel: Res<AudioChannel<MusicChannel>>,
+    mut audio_instances: ResMut<Assets<AudioInstance>>,
+) {
+    match audio_state.mode {
+        MusicMode::DriveScreen => {
+            start_track(&mut audio_state, &playlist, &mut now_playing, &music_channel, &mut audio_instances);
+            bevy::log::info!("Music started");
+        }
+        MusicMode::Playlist => {
+            // Back from game over, bring the music back up
+            if let Some(instance_handle) = &audio_state.instance
+                && let Some(instance) = audio_instances.get_mut(instance_handle)
+            {
+                instance.set_volume(synth::decibels(1.0), crossfade());
+            }
+        }
+    }
+}
+
+fn play_game_over_stinger(
+    audio_state: Res<ThemeState>,
+    music_channel: Res<AudioChannel<MusicChannel>>,
+    mut audio_instances: ResMut<Assets<AudioInstance>>,
+) {
+    if let Some(instance_handle) = &audio_state.instance
+        && let Some(instance) = audio_instances.get_mut(instance_handle)
+    {
+        instance.set_volume(synth::decibels(GAME_OVER_DUCK), AudioTween::default());
+    }
+    music_channel.play(audio_state.game_over_stinger.clone());
+}
+
+fn advance_playlist(
+    audio_state: Option<ResMut<ThemeState>>,
+    playlist: Option<ResMut<Playlist>>,
+    mut now_playing: ResMut<NowPlaying>,
+    music_channel: Res<AudioChannel<MusicChannel>>,
+    audio_sources: Res<Assets<AudioSource>>,
+    mut audio_instances: ResMut<Assets<AudioInstance>>,
+) {
+    let (Some(mut audio_state), Some(mut playlist)) = (audio_state, playlist) else {
+        return;
+    };
+    if audio_state.mode != MusicMode::Playlist || playlist.repeat == Repeat::One {
+        return;
+    }
+
+    let Some(instance_handle) = audio_state.instance.clone() else {
+        return;
+    };
+    let Some(track) = playlist.current() else {
+        return;
+    };
+
+    let finished = match audio_instances.get(&instance_handle) {
+        // Start the next track a crossfade before this one ends
+        Some(instance) => match (instance.state().position(), audio_sources.get(&track.source)) {
+            (Some(position), Some(source)) => {
+                position >= source.sound.duration().as_secs_f64() - CROSSFADE_SECONDS as f64
+            }
+            _ => false,
+        },
+        None => true,
+    };
+
+    if finished && !audio_state.crossfading {
+        audio_state.crossfading = true;
+        if playlist.advance() {
+            start_track(&mut audio_state, &playlist, &mut now_playing, &music_channel, &mut audio_instances);
+        }
     }
 }
 
-pub fn spawn_music_toggle(mut commands: Commands, asset_server: Res<AssetServer>) {
+pub fn spawn_music_toggle(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    existing_toggle: Query<(), With<MusicToggleButton>>,
+    settings: Res<AudioSettings>,
+) {
+    if !existing_toggle.is_empty() {
+        return;
+    }
+
     let font_handle = asset_server.load("font/NotoSansMono-Bold.ttf");
-    
+
     commands
         .spawn((
             Node {
@@ -51,13 +316,13 @@ pub fn spawn_music_toggle(mut commands: Commands, asset_server: Res<AssetServer>
                 top: Val::Px(10.),
                 ..default()
             },
-            MusicToggleButton,
         ))
         .with_children(|parent| {
             parent
                 .spawn((
                     Button,
                     BackgroundColor(Color::NONE),
+                    MusicToggleButton,
                 ))
                 .with_children(|button| {
                     button.spawn((
@@ -67,69 +332,98 @@ pub fn spawn_music_toggle(mut commands: Commands, asset_server: Res<AssetServer>
                             font_size: 16.,
                             ..default()
                         },
-                        TextColor(Color::srgb(0.9, 0.9, 0.9)),
+                        TextColor(music_toggle_color(settings.music_enabled)),
                         MusicToggleText,
                     ));
                 });
         });
 }
 
+fn spawn_now_playing_toast(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    existing_toast: Query<(), With<NowPlayingToast>>,
+) {
+    if !existing_toast.is_empty() {
+        return;
+    }
+
+    let font_handle = asset_server.load("font/NotoSansMono-Bold.ttf");
+
+    commands
+        .spawn(Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.),
+            bottom: Val::Px(10.),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn((
+                Text::default(),
+                TextFont {
+                    font: font_handle,
+                    font_size: 16.,
+                    ..default()
+                },
+                TextColor(Color::NONE),
+                NowPlayingToast { time_left: 0.0 },
+            ));
+        });
+}
+
+fn update_now_playing_toast(
+    time: Res<Time>,
+    mut now_playing: ResMut<NowPlaying>,
+    mut toast_query: Query<(&mut Text, &mut TextColor, &mut NowPlayingToast)>,
+) {
+    let Ok((mut text, mut text_color, mut toast)) = toast_query.single_mut() else {
+        return;
+    };
+
+    if let Some(title) = now_playing.title.take() {
+        text.0 = format!("♪ {}", title);
+        toast.time_left = NOW_PLAYING_SECONDS;
+    }
+
+    if toast.time_left > 0.0 {
+        toast.time_left = (toast.time_left - time.delta_secs()).max(0.0);
+        // Hold, then fade out over the last second
+        let alpha = toast.time_left.min(1.0);
+        *text_color = TextColor(Color::srgba(0.9, 0.9, 0.9, alpha));
+    }
+}
+
+fn music_toggle_color(music_enabled: bool) -> Color {
+    if music_enabled {
+        Color::srgb(0.9, 0.9, 0.9) // Bright when on
+    } else {
+        Color::srgb(0.4, 0.4, 0.4) // Dim when off
+    }
+}
+
 pub fn handle_music_toggle(
-    mut interaction_query: ButtonInteractionQuery,
-    mut text_color_query: Query<&mut TextColor, With<MusicToggleText>>,
-    mut audio_state: ResMut<ThemeState>,
-    audio: Res<Audio>,
-    mut audio_instances: ResMut<Assets<AudioInstance>>,
+    interaction_query: Query<&Interaction, (Changed<Interaction>, With<MusicToggleButton>)>,
+    mut settings: ResMut<AudioSettings>,
 ) {
-    for (interaction, children) in interaction_query.iter_mut() {
+    for interaction in interaction_query.iter() {
         if *interaction == Interaction::Pressed {
-            // Check if this button contains the music toggle text
-            if let Some(child) = children.first().copied()
-                && text_color_query.get(child).is_ok()
-            {
-                // Toggle music
-                if audio_state.is_playing {
-                    // Pause the music
-                    if let Some(instance_handle) = &audio_state.instance
-                        && let Some(instance) = audio_instances.get_mut(instance_handle)
-                    {
-                        instance.pause(AudioTween::default());
-                        audio_state.is_playing = false;
-                        bevy::log::info!("Music paused");
-                    }
-                } else {
-                    // Resume or restart the music
-                    if let Some(instance_handle) = &audio_state.instance {
-                        if let Some(instance) = audio_instances.get_mut(instance_handle) {
-                            instance.resume(AudioTween::default());
-                            audio_state.is_playing = true;
-                            bevy::log::info!("Music resumed");
-                        } else {
-                            // Instance was removed, restart the audio
-                            let instance = audio.play(audio_state.loop_handle.clone()).looped().handle();
-                            audio_state.instance = Some(instance);
-                            audio_state.is_playing = true;
-                            bevy::log::info!("Music restarted");
-                        }
-                    } else {
-                        // No instance, start the audio
-                        let instance = audio.play(audio_state.loop_handle.clone()).looped().handle();
-                        audio_state.instance = Some(instance);
-                        audio_state.is_playing = true;
-                        bevy::log::info!("Music started");
-                    }
-                }
-                
-                // Update button text color to indicate state
-                if let Ok(mut text_color) = text_color_query.get_mut(child) {
-                    if audio_state.is_playing {
-                        *text_color = TextColor(Color::srgb(0.9, 0.9, 0.9)); // Bright when on
-                    } else {
-                        *text_color = TextColor(Color::srgb(0.4, 0.4, 0.4)); // Dim when off
-                    }
-                }
-            }
+            // The music channel goes silent rather than pausing, so the playlist keeps its place
+            settings.music_enabled = !settings.music_enabled;
+            bevy::log::info!("Music {}", if settings.music_enabled { "on" } else { "off" });
         }
     }
 }
 
+/// Keeps the toggle in step with the setting, which the settings panel can also change
+fn update_music_toggle_text(
+    settings: Res<AudioSettings>,
+    mut text_color_query: Query<&mut TextColor, With<MusicToggleText>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    for mut text_color in text_color_query.iter_mut() {
+        *text_color = TextColor(music_toggle_color(settings.music_enabled));
+    }
+}
@@ -0,0 +1,286 @@
+use bevy::{input::touch::Touches, prelude::*, ui::UiGlobalTransform};
+use leafwing_input_manager::{plugin::InputManagerSystem, prelude::ActionState};
+
+use crate::{car::PlayerCar, input::CarAction, state::GameState};
+
+pub struct TouchControlsPlugin;
+
+impl Plugin for TouchControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TouchControls>()
+            .add_systems(Update, detect_touch_input)
+            .add_systems(
+                Update,
+                spawn_touch_controls
+                    .after(detect_touch_input)
+                    .run_if(in_state(GameState::Running)),
+            )
+            .add_systems(OnExit(GameState::Running), despawn_touch_controls)
+            // Runs after leafwing has read the keyboard and gamepad, so touches add to
+            // the same ActionState instead of being overwritten
+            .add_systems(
+                PreUpdate,
+                apply_touch_controls
+                    .in_set(InputManagerSystem::ManualControl)
+                    .run_if(in_state(GameState::Running)),
+            );
+    }
+}
+
+const JOYSTICK_SIZE: f32 = 160.0;
+const KNOB_SIZE: f32 = 64.0;
+// Camera orbit per pixel of drag, the same scale as a stick pushed all the way
+const CAMERA_DRAG_SENSITIVITY: f32 = 0.15;
+
+/// Touch state, the on-screen controls show up once a touch is seen
+#[derive(Resource, Default)]
+pub struct TouchControls {
+    pub detected: bool,
+    joystick_touch: Option<u64>,
+    camera_touch: Option<u64>,
+}
+
+#[derive(Component)]
+struct TouchControlsRoot;
+
+#[derive(Component)]
+struct TouchJoystick;
+
+#[derive(Component)]
+struct TouchJoystickKnob;
+
+/// On-screen button that holds down an action, or holds the throttle at a value, while touched
+#[derive(Component, Clone, Copy)]
+enum TouchButton {
+    Press(CarAction),
+    Throttle(f32),
+}
+
+impl TouchButton {
+    // These act once per tap, holding them shouldn't repeat
+    fn is_one_shot(&self) -> bool {
+        matches!(self, TouchButton::Press(CarAction::Reset | CarAction::Settings))
+    }
+}
+
+fn detect_touch_input(touches: Res<Touches>, mut touch_controls: ResMut<TouchControls>) {
+    if !touch_controls.detected && touches.any_just_pressed() {
+        touch_controls.detected = true;
+        bevy::log::info!("Touch input detected, showing touch controls");
+    }
+}
+
+fn spawn_touch_controls(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    touch_controls: Res<TouchControls>,
+    existing_controls: Query<(), With<TouchControlsRoot>>,
+) {
+    if !touch_controls.detected || !existing_controls.is_empty() {
+        return;
+    }
+
+    let font_handle = asset_server.load("font/NotoSansMono-Bold.ttf");
+
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                ..default()
+            },
+            TouchControlsRoot,
+        ))
+        .with_children(|parent| {
+            // Steering joystick, bottom left
+            parent
+                .spawn((
+                    Node {
+                        position_type: PositionType::Absolute,
+                        left: Val::Px(40.),
+                        bottom: Val::Px(40.),
+                        width: Val::Px(JOYSTICK_SIZE),
+                        height: Val::Px(JOYSTICK_SIZE),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    BorderRadius::MAX,
+                    BackgroundColor(Color::srgba(0.9, 0.9, 0.9, 0.15)),
+                    TouchJoystick,
+                ))
+                .with_children(|joystick| {
+                    joystick.spawn((
+                        Node {
+                            width: Val::Px(KNOB_SIZE),
+                            height: Val::Px(KNOB_SIZE),
+                            ..default()
+                        },
+                        BorderRadius::MAX,
+                        BackgroundColor(Color::srgba(0.9, 0.9, 0.9, 0.5)),
+                        TouchJoystickKnob,
+                    ));
+                });
+
+            // Pedals and buttons, bottom right
+            for (label, button, right, bottom, size) in [
+                ("gas", TouchButton::Throttle(1.0), 40., 40., 110.),
+                ("brake", TouchButton::Throttle(-1.0), 170., 40., 90.),
+                ("boost", TouchButton::Press(CarAction::Boost), 40., 170., 90.),
+                ("reset", TouchButton::Press(CarAction::Reset), 150., 160., 70.),
+            ] {
+                let position = Node {
+                    right: Val::Px(right),
+                    bottom: Val::Px(bottom),
+                    ..default()
+                };
+                spawn_touch_button(parent, &font_handle, label, button, position, size);
+            }
+
+            // No escape key on a phone, settings sits up top under the music toggle
+            let position = Node {
+                right: Val::Px(10.),
+                top: Val::Px(60.),
+                ..default()
+            };
+            spawn_touch_button(parent, &font_handle, "settings", TouchButton::Press(CarAction::Settings), position, 90.);
+        });
+}
+
+fn spawn_touch_button(
+    parent: &mut ChildSpawnerCommands,
+    font_handle: &Handle<Font>,
+    label: &str,
+    button: TouchButton,
+    position: Node,
+    size: f32,
+) {
+    parent
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Px(size),
+                height: Val::Px(size),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..position
+            },
+            BorderRadius::MAX,
+            BackgroundColor(Color::srgba(0.9, 0.9, 0.9, 0.15)),
+            button,
+        ))
+        .with_children(|button| {
+            button.spawn((
+                Text(label.to_string()),
+                TextFont {
+                    font: font_handle.clone(),
+                    font_size: 16.,
+                    ..default()
+                },
+                TextColor(Color::srgb(0.9, 0.9, 0.9)),
+            ));
+        });
+}
+
+fn despawn_touch_controls(
+    mut commands: Commands,
+    mut touch_controls: ResMut<TouchControls>,
+    query: Query<Entity, With<TouchControlsRoot>>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+    touch_controls.joystick_touch = None;
+    touch_controls.camera_touch = None;
+}
+
+/// Center and half size of a UI node in logical pixels, the same space as touch positions
+fn node_bounds(node: &ComputedNode, transform: &UiGlobalTransform) -> (Vec2, Vec2) {
+    let scale = node.inverse_scale_factor();
+    (transform.translation * scale, node.size() * scale * 0.5)
+}
+
+fn contains(bounds: (Vec2, Vec2), point: Vec2) -> bool {
+    let (center, half_size) = bounds;
+    (point - center).abs().cmple(half_size).all()
+}
+
+fn apply_touch_controls(
+    touches: Res<Touches>,
+    mut touch_controls: ResMut<TouchControls>,
+    joystick_query: Query<(&ComputedNode, &UiGlobalTransform), With<TouchJoystick>>,
+    mut knob_query: Query<&mut Node, With<TouchJoystickKnob>>,
+    mut button_query: Query<(&TouchButton, &ComputedNode, &UiGlobalTransform, &mut BackgroundColor)>,
+    mut car_query: Query<(&PlayerCar, &mut ActionState<CarAction>)>,
+) {
+    let Ok((joystick_node, joystick_transform)) = joystick_query.single() else {
+        return;
+    };
+    let joystick_bounds = node_bounds(joystick_node, joystick_transform);
+
+    // Hand out new touches: the joystick, a button, or else dragging the camera
+    for touch in touches.iter_just_pressed() {
+        let on_button = button_query
+            .iter()
+            .any(|(_, node, transform, _)| contains(node_bounds(node, transform), touch.position()));
+
+        if touch_controls.joystick_touch.is_none() && contains(joystick_bounds, touch.position()) {
+            touch_controls.joystick_touch = Some(touch.id());
+        } else if touch_controls.camera_touch.is_none() && !on_button {
+            touch_controls.camera_touch = Some(touch.id());
+        }
+    }
+
+    for touch in touches.iter_just_released().chain(touches.iter_just_canceled()) {
+        if touch_controls.joystick_touch == Some(touch.id()) {
+            touch_controls.joystick_touch = None;
+        }
+        if touch_controls.camera_touch == Some(touch.id()) {
+            touch_controls.camera_touch = None;
+        }
+    }
+
+    // There's one screen to touch, it drives the first player's car
+    let Some((_, mut action_state)) = car_query.iter_mut().find(|(player, _)| player.0 == 0) else {
+        return;
+    };
+
+    // Joystick steers through the same axis as the gamepad stick
+    let (center, half_size) = joystick_bounds;
+    let stick = touch_controls
+        .joystick_touch
+        .and_then(|id| touches.get_pressed(id))
+        .map(|touch| ((touch.position() - center) / half_size.x).clamp_length_max(1.0))
+        .unwrap_or(Vec2::ZERO);
+    if stick != Vec2::ZERO {
+        action_state.set_axis_pair(&CarAction::TurnAxis, Vec2::new(stick.x, 0.0));
+    }
+    if let Ok(mut knob) = knob_query.single_mut() {
+        let travel = (JOYSTICK_SIZE - KNOB_SIZE) * 0.5;
+        knob.left = Val::Px(stick.x * travel);
+        knob.top = Val::Px(stick.y * travel);
+    }
+
+    for (button, node, transform, mut background) in button_query.iter_mut() {
+        let bounds = node_bounds(node, transform);
+        let held = touches.iter().any(|touch| contains(bounds, touch.position()));
+        let tapped = touches.iter_just_pressed().any(|touch| contains(bounds, touch.position()));
+
+        if (button.is_one_shot() && tapped) || (!button.is_one_shot() && held) {
+            match *button {
+                TouchButton::Press(action) => action_state.press(&action),
+                TouchButton::Throttle(value) => action_state.set_value(&CarAction::Throttle, value),
+            }
+        }
+        let alpha = if held { 0.4 } else { 0.15 };
+        *background = BackgroundColor(Color::srgba(0.9, 0.9, 0.9, alpha));
+    }
+
+    // Dragging anywhere else orbits the camera
+    if let Some(touch) = touch_controls.camera_touch.and_then(|id| touches.get_pressed(id)) {
+        let orbit = (touch.delta() * CAMERA_DRAG_SENSITIVITY).clamp(Vec2::splat(-3.0), Vec2::splat(3.0));
+        // Screen y points down, the stick's points up
+        action_state.set_axis_pair(&CarAction::CameraOrbit, Vec2::new(orbit.x, -orbit.y));
+    }
+}
@@ -0,0 +1,333 @@
+use bevy::{light::NotShadowCaster, prelude::*};
+use bevy_rapier3d::prelude::{CollidingEntities, QueryFilter, ReadRapierContext, Sensor, Velocity};
+
+use crate::{
+    car::{Car, WHEEL_OFFSETS},
+    impacts::on_ground,
+    movement::{apply_movement, Drift},
+    rng::Rng,
+    state::GameState,
+    surface::{GroundSurface, Surface},
+};
+
+// Effect entities are pooled and recycled oldest-first, so the entity count never
+// grows with play time and the WebGL2 build keeps a steady frame rate
+const MAX_TRACK_SEGMENTS: usize = 384;
+const MAX_DUST_PARTICLES: usize = 96;
+
+const TRACK_SEGMENT_LENGTH: f32 = 1.2;
+const TRACK_WIDTH: f32 = 0.7;
+const TRACK_LIFETIME: f32 = 12.0;
+// Tracks fade by swapping between a few shared materials instead of one material per segment
+const TRACK_FADE_STEPS: usize = 6;
+
+const DUST_LIFETIME: f32 = 1.2;
+// Dust particles per meter traveled on the dustiest surface
+const DUST_PER_METER: f32 = 0.25;
+
+pub struct TracksPlugin;
+
+impl Plugin for TracksPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Running), setup_effect_pools)
+            .add_systems(
+                Update,
+                (
+                    emit_tire_tracks.after(apply_movement),
+                    emit_dust.after(apply_movement),
+                )
+                    .run_if(in_state(GameState::Running)),
+            )
+            // Keep fading while the game over screen is up
+            .add_systems(
+                Update,
+                (fade_tire_tracks, update_dust).run_if(resource_exists::<TireTrackPool>),
+            );
+    }
+}
+
+/// Per-car state for the tire tracks and dust it leaves behind
+#[derive(Component, Debug, Default)]
+pub struct WheelEffects {
+    last_marks: [Option<Vec3>; 4],
+    dust_accumulator: f32,
+}
+
+#[derive(Resource)]
+struct TireTrackPool {
+    segments: Vec<Entity>,
+    next: usize,
+    materials: Vec<Handle<StandardMaterial>>,
+}
+
+#[derive(Component, Default)]
+struct TrackSegment {
+    age: f32,
+    fade_step: usize,
+}
+
+#[derive(Resource)]
+struct DustPool {
+    particles: Vec<Entity>,
+    next: usize,
+    // One material per surface, indexed by `Surface as usize`
+    materials: Vec<Handle<StandardMaterial>>,
+    rng: Rng,
+}
+
+#[derive(Component, Default)]
+struct DustParticle {
+    velocity: Vec3,
+    age: f32,
+    size: f32,
+}
+
+fn setup_effect_pools(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    existing_pool: Option<Res<TireTrackPool>>,
+) {
+    // Pools survive GameOver -> Running, only build them once
+    if existing_pool.is_some() {
+        return;
+    }
+
+    let track_mesh = meshes.add(Plane3d::default().mesh().size(1.0, 1.0));
+    let track_materials: Vec<Handle<StandardMaterial>> = (0..TRACK_FADE_STEPS)
+        .map(|step| {
+            let alpha = 0.5 * (1.0 - step as f32 / TRACK_FADE_STEPS as f32);
+            materials.add(StandardMaterial {
+                base_color: Color::srgba(0.12, 0.06, 0.02, alpha),
+                alpha_mode: AlphaMode::Blend,
+                perceptual_roughness: 1.0,
+                ..default()
+            })
+        })
+        .collect();
+
+    let segments = (0..MAX_TRACK_SEGMENTS)
+        .map(|_| {
+            commands
+                .spawn((
+                    Mesh3d(track_mesh.clone()),
+                    MeshMaterial3d(track_materials[0].clone()),
+                    Transform::default(),
+                    Visibility::Hidden,
+                    NotShadowCaster,
+                    TrackSegment::default(),
+                ))
+                .id()
+        })
+        .collect();
+
+    commands.insert_resource(TireTrackPool {
+        segments,
+        next: 0,
+        materials: track_materials,
+    });
+
+    let dust_mesh = meshes.add(Sphere::new(1.0).mesh().uv(8, 6));
+    let dust_materials: Vec<Handle<StandardMaterial>> = Surface::ALL
+        .iter()
+        .map(|surface| {
+            materials.add(StandardMaterial {
+                base_color: surface.properties().dust_color.with_alpha(0.35),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..default()
+            })
+        })
+        .collect();
+
+    let particles = (0..MAX_DUST_PARTICLES)
+        .map(|_| {
+            commands
+                .spawn((
+                    Mesh3d(dust_mesh.clone()),
+                    MeshMaterial3d(dust_materials[0].clone()),
+                    Transform::default(),
+                    Visibility::Hidden,
+                    NotShadowCaster,
+                    DustParticle::default(),
+                ))
+                .id()
+        })
+        .collect();
+
+    commands.insert_resource(DustPool {
+        particles,
+        next: 0,
+        materials: dust_materials,
+        rng: Rng::default(),
+    });
+}
+
+fn emit_tire_tracks(
+    read_rapier_context: ReadRapierContext,
+    mut pool: ResMut<TireTrackPool>,
+    mut car_query: Query<(Entity, &GlobalTransform, &CollidingEntities, &GroundSurface, &Drift, &mut WheelEffects), With<Car>>,
+    mut segment_query: Query<(&mut Transform, &mut Visibility, &mut TrackSegment, &mut MeshMaterial3d<StandardMaterial>)>,
+    sensor_query: Query<(), With<Sensor>>,
+) {
+    let Ok(rapier_context) = read_rapier_context.single() else {
+        return;
+    };
+
+    for (car_entity, global_transform, colliding_entities, ground_surface, drift, mut effects) in car_query.iter_mut() {
+        // Nothing to mark in the air, and rock doesn't hold tracks
+        if !on_ground(colliding_entities, &sensor_query) || ground_surface.surface == Surface::Rock {
+            effects.last_marks = [None; 4];
+            continue;
+        }
+
+        let filter = QueryFilter::default().exclude_rigid_body(car_entity).exclude_sensors();
+        for (wheel, offset) in WHEEL_OFFSETS.iter().enumerate() {
+            // Cast from a little above the wheel so the ray starts outside the terrain
+            let ray_origin = global_transform.transform_point(*offset + Vec3::Y);
+            let Some((_entity, hit)) = rapier_context.cast_ray_and_get_normal(ray_origin, Vec3::NEG_Y, 2.5, true, filter) else {
+                effects.last_marks[wheel] = None;
+                continue;
+            };
+
+            let Some(last_mark) = effects.last_marks[wheel] else {
+                effects.last_marks[wheel] = Some(hit.point);
+                continue;
+            };
+
+            let step = hit.point - last_mark;
+            let length = step.length();
+            if length < TRACK_SEGMENT_LENGTH {
+                continue;
+            }
+            effects.last_marks[wheel] = Some(hit.point);
+
+            // The car was reset or teleported, don't bridge the gap
+            if length > TRACK_SEGMENT_LENGTH * 4.0 {
+                continue;
+            }
+
+            let entity = pool.segments[pool.next];
+            pool.next = (pool.next + 1) % pool.segments.len();
+
+            if let Ok((mut transform, mut visibility, mut segment, mut material)) = segment_query.get_mut(entity) {
+                // The plane mesh lies in local XZ: align local Y with the ground and local X with the travel direction
+                let normal = hit.normal.normalize_or(Vec3::Y);
+                let along = (step - normal * step.dot(normal)).normalize_or(Vec3::X);
+                let across = along.cross(normal);
+
+                transform.translation = (last_mark + hit.point) / 2.0 + normal * 0.03;
+                transform.rotation = Quat::from_mat3(&Mat3::from_cols(along, normal, across));
+                // Sliding tires smear a wider mark
+                let width = if drift.is_drifting { TRACK_WIDTH * 1.4 } else { TRACK_WIDTH };
+                transform.scale = Vec3::new(length, 1.0, width);
+
+                segment.age = 0.0;
+                segment.fade_step = 0;
+                material.0 = pool.materials[0].clone();
+                *visibility = Visibility::Visible;
+            }
+        }
+    }
+}
+
+fn fade_tire_tracks(
+    time: Res<Time>,
+    pool: Res<TireTrackPool>,
+    mut segment_query: Query<(&mut Visibility, &mut TrackSegment, &mut MeshMaterial3d<StandardMaterial>)>,
+) {
+    for (mut visibility, mut segment, mut material) in segment_query.iter_mut() {
+        if *visibility == Visibility::Hidden {
+            continue;
+        }
+
+        segment.age += time.delta_secs();
+        if segment.age >= TRACK_LIFETIME {
+            *visibility = Visibility::Hidden;
+            continue;
+        }
+
+        let fade_step = ((segment.age / TRACK_LIFETIME) * TRACK_FADE_STEPS as f32) as usize;
+        if fade_step != segment.fade_step {
+            segment.fade_step = fade_step;
+            material.0 = pool.materials[fade_step].clone();
+        }
+    }
+}
+
+fn emit_dust(
+    mut pool: ResMut<DustPool>,
+    time: Res<Time>,
+    mut car_query: Query<(&GlobalTransform, &Velocity, &CollidingEntities, &GroundSurface, &Drift, &mut WheelEffects), With<Car>>,
+    mut particle_query: Query<(&mut Transform, &mut Visibility, &mut DustParticle, &mut MeshMaterial3d<StandardMaterial>)>,
+    sensor_query: Query<(), With<Sensor>>,
+) {
+    let pool = &mut *pool;
+
+    for (global_transform, velocity, colliding_entities, ground_surface, drift, mut effects) in car_query.iter_mut() {
+        if !on_ground(colliding_entities, &sensor_query) {
+            continue;
+        }
+
+        let properties = ground_surface.surface.properties();
+        let speed = velocity.linvel.length();
+        let drift_factor = if drift.is_drifting { 2.0 } else { 1.0 };
+
+        effects.dust_accumulator += speed * time.delta_secs() * properties.dust * DUST_PER_METER * drift_factor;
+        // Don't dump a burst of particles after a long frame
+        effects.dust_accumulator = effects.dust_accumulator.min(8.0);
+
+        while effects.dust_accumulator >= 1.0 {
+            effects.dust_accumulator -= 1.0;
+
+            let entity = pool.particles[pool.next];
+            // Alternate between the two rear wheels
+            let wheel = WHEEL_OFFSETS[2 + pool.next % 2];
+            pool.next = (pool.next + 1) % pool.particles.len();
+
+            if let Ok((mut transform, mut visibility, mut particle, mut material)) = particle_query.get_mut(entity) {
+                let jitter = Vec3::new(
+                    pool.rng.range(-1.0, 1.0),
+                    pool.rng.range(0.0, 1.0),
+                    pool.rng.range(-1.0, 1.0),
+                );
+
+                // Plumes get bigger the faster the car goes
+                particle.size = (0.6 + speed * 0.03) * (0.5 + properties.dust);
+                particle.velocity = -velocity.linvel * 0.15 + Vec3::Y * pool.rng.range(1.0, 4.0) + jitter * 2.0;
+                particle.age = 0.0;
+
+                transform.translation = global_transform.transform_point(wheel) + jitter * 0.5;
+                transform.scale = Vec3::splat(particle.size * 0.3);
+                material.0 = pool.materials[ground_surface.surface as usize].clone();
+                *visibility = Visibility::Visible;
+            }
+        }
+    }
+}
+
+fn update_dust(
+    time: Res<Time>,
+    mut particle_query: Query<(&mut Transform, &mut Visibility, &mut DustParticle)>,
+) {
+    let dt = time.delta_secs();
+
+    for (mut transform, mut visibility, mut particle) in particle_query.iter_mut() {
+        if *visibility == Visibility::Hidden {
+            continue;
+        }
+
+        particle.age += dt;
+        if particle.age >= DUST_LIFETIME {
+            *visibility = Visibility::Hidden;
+            continue;
+        }
+
+        transform.translation += particle.velocity * dt;
+        particle.velocity *= (1.0 - 1.5 * dt).max(0.0);
+
+        // Billow out quickly, then shrink away
+        let t = particle.age / DUST_LIFETIME;
+        transform.scale = Vec3::splat(particle.size * (0.3 + 0.7 * t.sqrt()) * (1.0 - t));
+    }
+}
@@ -0,0 +1,268 @@
+use bevy::{
+    light::NotShadowCaster,
+    pbr::{DistanceFog, FogFalloff},
+    prelude::*,
+};
+use bevy_kira_audio::{AudioChannel, AudioControl, AudioInstance, AudioSource, AudioTween};
+
+use crate::{rng::Rng, settings::SfxChannel, state::GameState, synth};
+
+pub struct WeatherPlugin;
+
+impl Plugin for WeatherPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Running), setup_weather)
+            .add_systems(
+                Update,
+                (
+                    update_weather,
+                    apply_weather_fog.after(update_weather),
+                    update_sand_particles.after(update_weather),
+                    update_wind_audio.after(update_weather),
+                )
+                    .run_if(resource_exists::<Weather>),
+            );
+    }
+}
+
+const MAX_SAND_PARTICLES: usize = 160;
+// Sand particles live in a box this size around the camera and wrap at its edges
+const SAND_BOX_HALF_EXTENT: Vec3 = Vec3::new(40.0, 15.0, 40.0);
+// Peak sideways push on the car at full storm strength
+const WIND_FORCE: f32 = 700.0;
+// Storms take this long to fully build up or die down
+const STORM_RAMP_SECONDS: f32 = 8.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WeatherKind {
+    #[default]
+    Clear,
+    Sandstorm,
+}
+
+/// How a map decides its weather: rolled from a seed, or pinned to one kind
+#[derive(Resource, Debug, Clone)]
+pub enum WeatherSchedule {
+    Random { seed: u64 },
+    Fixed(WeatherKind),
+}
+
+#[derive(Resource, Debug)]
+pub struct Weather {
+    pub kind: WeatherKind,
+    /// How far the current weather has built up, 0 clear to 1 full storm
+    pub intensity: f32,
+    /// Horizontal wind direction for the current storm
+    pub wind_direction: Vec3,
+    time_left: f32,
+    gust_time: f32,
+    rng: Rng,
+}
+
+impl Weather {
+    /// Force the wind puts on a car right now
+    pub fn wind_force(&self) -> Vec3 {
+        let gust = 0.75 + 0.25 * (self.gust_time * 0.9).sin() * (self.gust_time * 0.37).cos();
+        self.wind_direction * WIND_FORCE * self.intensity * gust
+    }
+}
+
+#[derive(Resource)]
+struct WeatherAudio {
+    wind_instance: Handle<AudioInstance>,
+}
+
+#[derive(Component)]
+struct SandParticle {
+    offset: Vec3,
+}
+
+fn setup_weather(
+    mut commands: Commands,
+    schedule: Res<WeatherSchedule>,
+    existing_weather: Option<Res<Weather>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut audio_sources: ResMut<Assets<AudioSource>>,
+    sfx_channel: Res<AudioChannel<SfxChannel>>,
+) {
+    // Weather keeps going across GameOver -> Running
+    if existing_weather.is_some() {
+        return;
+    }
+
+    let (kind, seed) = match *schedule {
+        WeatherSchedule::Random { seed } => (WeatherKind::Clear, seed),
+        WeatherSchedule::Fixed(kind) => (kind, 0),
+    };
+    let mut rng = Rng::new(seed);
+    let angle = rng.range(0.0, std::f32::consts::TAU);
+    let time_left = rng.range(60.0, 180.0);
+
+    commands.insert_resource(Weather {
+        kind,
+        intensity: 0.0,
+        wind_direction: Vec3::new(angle.cos(), 0.0, angle.sin()),
+        time_left,
+        gust_time: 0.0,
+        rng,
+    });
+
+    // Streaks of sand, stretched along their local x which gets pointed down the wind
+    let particle_mesh = meshes.add(Cuboid::new(1.2, 0.05, 0.05));
+    let particle_material = materials.add(StandardMaterial {
+        base_color: Color::srgba(0.8, 0.6, 0.4, 0.6),
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..default()
+    });
+    let mut particle_rng = Rng::new(seed ^ 0x5A4D);
+    for _ in 0..MAX_SAND_PARTICLES {
+        let offset = Vec3::new(
+            particle_rng.range(-1.0, 1.0),
+            particle_rng.range(-1.0, 1.0),
+            particle_rng.range(-1.0, 1.0),
+        ) * SAND_BOX_HALF_EXTENT;
+        commands.spawn((
+            Mesh3d(particle_mesh.clone()),
+            MeshMaterial3d(particle_material.clone()),
+            Transform::default(),
+            Visibility::Hidden,
+            NotShadowCaster,
+            SandParticle { offset },
+        ));
+    }
+
+    // The wind loop always plays, its volume follows the storm
+    let wind_source = audio_sources.add(synth::audio_source(synth::wind(8.0, seed)));
+    let wind_instance = sfx_channel
+        .play(wind_source)
+        .looped()
+        .with_volume(synth::decibels(0.0))
+        .handle();
+    commands.insert_resource(WeatherAudio { wind_instance });
+}
+
+fn update_weather(time: Res<Time>, schedule: Res<WeatherSchedule>, mut weather: ResMut<Weather>) {
+    let dt = time.delta_secs();
+    weather.gust_time += dt;
+
+    match *schedule {
+        WeatherSchedule::Fixed(kind) => weather.kind = kind,
+        WeatherSchedule::Random { .. } => {
+            weather.time_left -= dt;
+            if weather.time_left <= 0.0 {
+                let weather = &mut *weather;
+                match weather.kind {
+                    WeatherKind::Clear => {
+                        weather.kind = WeatherKind::Sandstorm;
+                        weather.time_left = weather.rng.range(30.0, 90.0);
+                        let angle = weather.rng.range(0.0, std::f32::consts::TAU);
+                        weather.wind_direction = Vec3::new(angle.cos(), 0.0, angle.sin());
+                        bevy::log::info!("Sandstorm rolling in for {:.0}s", weather.time_left);
+                    }
+                    WeatherKind::Sandstorm => {
+                        weather.kind = WeatherKind::Clear;
+                        weather.time_left = weather.rng.range(90.0, 240.0);
+                        bevy::log::info!("Sandstorm clearing");
+                    }
+                }
+            }
+        }
+    }
+
+    let target = match weather.kind {
+        WeatherKind::Clear => 0.0,
+        WeatherKind::Sandstorm => 1.0,
+    };
+    let step = dt / STORM_RAMP_SECONDS;
+    weather.intensity = if weather.intensity < target {
+        (weather.intensity + step).min(target)
+    } else {
+        (weather.intensity - step).max(target)
+    };
+}
+
+fn apply_weather_fog(
+    mut commands: Commands,
+    weather: Res<Weather>,
+    mut camera_query: Query<(Entity, Option<&mut DistanceFog>), With<Camera3d>>,
+) {
+    for (entity, fog) in camera_query.iter_mut() {
+        if weather.intensity <= 0.0 {
+            if fog.is_some() {
+                commands.entity(entity).remove::<DistanceFog>();
+            }
+            continue;
+        }
+
+        // Visibility drops from the far dunes down to a few car lengths
+        let start = 600.0 + (10.0 - 600.0) * weather.intensity;
+        let end = 2500.0 + (120.0 - 2500.0) * weather.intensity;
+        let falloff = FogFalloff::Linear { start, end };
+        let color = Color::srgba(0.6, 0.45, 0.3, weather.intensity.min(1.0));
+
+        match fog {
+            Some(mut fog) => {
+                fog.falloff = falloff;
+                fog.color = color;
+            }
+            None => {
+                commands.entity(entity).insert(DistanceFog {
+                    color,
+                    falloff,
+                    ..default()
+                });
+            }
+        }
+    }
+}
+
+fn update_sand_particles(
+    time: Res<Time>,
+    weather: Res<Weather>,
+    camera_query: Query<&GlobalTransform, With<Camera3d>>,
+    mut particle_query: Query<(&mut Transform, &mut Visibility, &mut SandParticle)>,
+) {
+    let Some(camera_transform) = camera_query.iter().next() else {
+        return;
+    };
+
+    let center = camera_transform.translation();
+    let visible_count = (weather.intensity * MAX_SAND_PARTICLES as f32) as usize;
+    let velocity = weather.wind_direction * 35.0 + Vec3::NEG_Y * 2.0;
+    let rotation = Quat::from_rotation_arc(Vec3::X, weather.wind_direction.normalize_or(Vec3::X));
+
+    for (index, (mut transform, mut visibility, mut particle)) in particle_query.iter_mut().enumerate() {
+        if index >= visible_count {
+            *visibility = Visibility::Hidden;
+            continue;
+        }
+
+        // Blow along with the wind and wrap around the box so the camera is always in the storm
+        let offset = particle.offset + velocity * time.delta_secs();
+        particle.offset = Vec3::new(
+            wrap(offset.x, SAND_BOX_HALF_EXTENT.x),
+            wrap(offset.y, SAND_BOX_HALF_EXTENT.y),
+            wrap(offset.z, SAND_BOX_HALF_EXTENT.z),
+        );
+
+        transform.translation = center + particle.offset;
+        transform.rotation = rotation;
+        *visibility = Visibility::Visible;
+    }
+}
+
+fn wrap(value: f32, half_extent: f32) -> f32 {
+    (value + half_extent).rem_euclid(half_extent * 2.0) - half_extent
+}
+
+fn update_wind_audio(
+    weather: Res<Weather>,
+    weather_audio: Res<WeatherAudio>,
+    mut audio_instances: ResMut<Assets<AudioInstance>>,
+) {
+    if let Some(instance) = audio_instances.get_mut(&weather_audio.wind_instance) {
+        instance.set_volume(synth::decibels(weather.intensity * 0.8), AudioTween::default());
+    }
+}